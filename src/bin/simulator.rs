@@ -0,0 +1,102 @@
+//! Plays many games between scripted players and reports how they went.
+//!
+//! Usage: `cargo run --release --bin simulator -- [flags] [setup option value]...`
+//!
+//! Flags:
+//! - `--games <count>` Number of games to play, 1000 by default
+//! - `--players <count>` Players in each game, 7 by default
+//! - `--seed <seed>` Seed of the first game, 0 by default. Game `n` is seeded with `seed + n`
+//! - `--mafia <policy>`, `--town <policy>` How the Mafia and everyone else play. One of
+//!   `random`, `nobody` or `coordinated`, `coordinated` by default for the Mafia and `random`
+//!   for the town
+//! - `--max-phases <count>` Phases after which a game is given up on, 200 by default
+//! - `--list` Print the seed and outcome of every game, so that one can be replayed
+//!
+//! The setup options are the same as the lobby's `/set` command, e.g. `mafia 2 reveal on`
+
+use std::{env, process};
+use that_mafia_game::{
+    game::game_setup::GameSetup,
+    simulator::{policies::get_policy_by_name, SimulationReport, Simulator},
+};
+
+fn main() {
+    let mut game_count = 1000;
+    let mut first_seed = 0;
+    let mut list_games = false;
+    let mut simulator = Simulator {
+        player_count: 7,
+        setup: GameSetup::default(),
+        mafia_policy: get_policy_by_name("coordinated").unwrap(),
+        town_policy: get_policy_by_name("random").unwrap(),
+        max_phases: 200,
+    };
+
+    let mut setup_options = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--list" => list_games = true,
+            "--games" => game_count = parse_number(&arg, args.next()),
+            "--players" => simulator.player_count = parse_number(&arg, args.next()),
+            "--seed" => first_seed = parse_number(&arg, args.next()),
+            "--max-phases" => simulator.max_phases = parse_number(&arg, args.next()),
+            "--mafia" | "--town" => {
+                let policy = get_policy_by_name(&args.next().unwrap_or_default())
+                    .unwrap_or_else(|message| exit_with(&message));
+                if arg == "--mafia" {
+                    simulator.mafia_policy = policy;
+                } else {
+                    simulator.town_policy = policy;
+                }
+            }
+            _ => setup_options.push((arg, args.next().unwrap_or_default())),
+        }
+    }
+
+    // Role counts start from the preset for the final player count
+    for (option, value) in setup_options {
+        if let Err(message) = simulator
+            .setup
+            .set_option(&option, &value, simulator.player_count)
+        {
+            exit_with(&message);
+        }
+    }
+    if simulator.player_count < 3 {
+        exit_with("Need 3 or more players");
+    }
+    if let Err(message) = simulator.setup.validate(simulator.player_count) {
+        exit_with(&message);
+    }
+
+    println!("{}\n", simulator.setup.describe(simulator.player_count));
+
+    let mut summary = SimulationReport::default();
+    for seed in (first_seed..).take(game_count) {
+        let report = simulator.run_game(seed);
+        if list_games {
+            println!(
+                "Seed {}: {} after {} phases",
+                report.seed,
+                report.winner.as_deref().unwrap_or("Unfinished"),
+                report.phase_count
+            );
+        }
+        summary.add_game(report);
+    }
+
+    print!("{summary}");
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+    value
+        .parse()
+        .unwrap_or_else(|_| exit_with(&format!("Expected a number after {flag}, got \"{value}\"")))
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1)
+}
@@ -0,0 +1,224 @@
+//! Plays a whole game in the terminal, with every player taking turns at the same keyboard.
+//!
+//! Usage: `cargo run --bin terminal -- [player count] [setup option value]...`, where the setup
+//! options are the same as the lobby's `/set` command, e.g. `terminal 6 mafia 2 reveal on`.
+//! `--seed <n>` replays the game that was dealt with that seed
+
+use rand::Rng;
+use std::{
+    env,
+    io::{self, BufRead, Write},
+    process,
+};
+use that_mafia_game::{
+    game::{game_setup::GameSetup, game_v1::GameV1, Game, GamePhase, Player, Role},
+    lobby_manager::Lobby,
+};
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let player_count = match args.next() {
+        Some(arg) => arg
+            .parse::<usize>()
+            .unwrap_or_else(|_| exit_with(&format!("Expected a number of players, got \"{arg}\""))),
+        None => 5,
+    };
+
+    let mut setup = GameSetup::default();
+    let mut seed = rand::thread_rng().gen();
+    while let Some(option) = args.next() {
+        let value = args.next().unwrap_or_default();
+        if option == "--seed" {
+            seed = value
+                .parse()
+                .unwrap_or_else(|_| exit_with(&format!("Expected a seed, got \"{value}\"")));
+        } else if let Err(message) = setup.set_option(&option, &value, player_count) {
+            exit_with(&message);
+        }
+    }
+    if player_count < 3 {
+        exit_with("Need 3 or more players");
+    }
+    if let Err(message) = setup.validate(player_count) {
+        exit_with(&message);
+    }
+
+    let lobby = Lobby::with_players(player_count, setup);
+    println!("{}", lobby.setup.describe(player_count));
+    println!("Seed: {seed}\n");
+
+    let mut game: Box<dyn Game> = Box::new(GameV1::from_lobby(&lobby, seed));
+    let mut input = Input::new();
+    loop {
+        print_transition(game.as_ref());
+        deliver_private_messages(game.as_ref(), &mut input);
+
+        match game.get_phase().clone() {
+            GamePhase::Night { .. } => play_night(game.as_mut(), &mut input),
+            GamePhase::Voting { .. } => play_voting(game.as_mut(), &mut input),
+            GamePhase::Trial { .. } => play_trial(game.as_mut(), &mut input),
+            // Players cannot talk to each other here, so their speeches are skipped
+            GamePhase::Defense { .. } | GamePhase::LastWords { .. } => {}
+            GamePhase::Ending => break,
+        }
+
+        game.end_or_force_phase();
+    }
+
+    println!("\n=== Game summary ===\n{}", game.get_summary());
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1)
+}
+
+/// Reads the players' inputs from stdin, exiting once it is closed
+struct Input {
+    lines: io::Lines<io::StdinLock<'static>>,
+}
+
+impl Input {
+    fn new() -> Input {
+        Input {
+            lines: io::stdin().lock().lines(),
+        }
+    }
+
+    fn read_line(&mut self, prompt: &str) -> String {
+        print!("{prompt}");
+        io::stdout().flush().ok();
+        match self.lines.next() {
+            Some(Ok(line)) => line.trim().to_string(),
+            _ => process::exit(0),
+        }
+    }
+
+    /// Asks for one or more comma separated option numbers. An empty line skips the choice
+    fn read_choices(&mut self, option_count: usize, allow_multiple: bool) -> Option<Vec<usize>> {
+        loop {
+            let line = self.read_line("> ");
+            if line.is_empty() {
+                return None;
+            }
+
+            let choices = line
+                .split(',')
+                .map(|choice| choice.trim().parse::<usize>().ok())
+                .collect::<Option<Vec<_>>>()
+                .filter(|choices| allow_multiple || choices.len() == 1)
+                .filter(|choices| choices.iter().all(|c| (1..=option_count).contains(c)));
+
+            match choices {
+                Some(choices) => return Some(choices.iter().map(|c| c - 1).collect()),
+                None if allow_multiple => {
+                    println!("Enter option numbers between 1 and {option_count}, e.g. 1,3")
+                }
+                None => println!("Enter an option number between 1 and {option_count}"),
+            }
+        }
+    }
+}
+
+fn print_transition(game: &dyn Game) {
+    println!("\n=== {} ===", game.get_transition_message());
+}
+
+/// Shows each player the private messages from the latest transition, e.g. investigation
+/// results, on a turn of their own so that the other players can look away
+fn deliver_private_messages(game: &dyn Game, input: &mut Input) {
+    let mut private_messages = game.get_private_messages();
+    let mut players = game.get_players().into_iter().cloned().collect::<Vec<_>>();
+    players.sort_by_key(|p| p.player_id.0);
+
+    for player in players {
+        let Some(messages) = private_messages.remove(&player.player_id) else {
+            continue;
+        };
+        start_turn(&player, input);
+        for message in messages {
+            println!("{message}");
+        }
+    }
+}
+
+/// Announces whose turn it is, so that the other players can look away
+fn start_turn(player: &Player, input: &mut Input) {
+    input.read_line(&format!(
+        "\n--- {} ({}), press enter when ready ---",
+        player.username, player.role
+    ));
+}
+
+fn print_options<T: AsRef<str>>(options: &[T]) {
+    for (i, option) in options.iter().enumerate() {
+        println!("  {}. {}", i + 1, option.as_ref());
+    }
+}
+
+fn play_night(game: &mut dyn Game, input: &mut Input) {
+    let mut players = game.get_players().into_iter().cloned().collect::<Vec<_>>();
+    players.sort_by_key(|p| p.player_id.0);
+
+    for player in players {
+        let Some((prompt, options)) = game.get_night_actions().remove(&player.player_id) else {
+            continue;
+        };
+        start_turn(&player, input);
+        if player.role == Role::Mafia {
+            if let Ok((_, summary)) = game.get_team_picks() {
+                println!("{summary}");
+            }
+        }
+        println!("{prompt}");
+        if options.is_empty() {
+            continue;
+        }
+
+        print_options(&options.iter().map(|o| &o.1).collect::<Vec<_>>());
+        if let Some(choices) = input.read_choices(options.len(), false) {
+            let target_id = options[choices[0]].0;
+            if let Err(err) = game.add_night_action(player.player_id, target_id) {
+                println!("{err}");
+            }
+        }
+    }
+}
+
+fn play_voting(game: &mut dyn Game, input: &mut Input) {
+    let (Ok(voters), Ok(options)) = (game.get_voters(), game.get_vote_options()) else {
+        return;
+    };
+    let voters = voters.into_iter().cloned().collect::<Vec<_>>();
+    let allow_multiple = game.get_setup().allow_multiple_votes;
+
+    for voter in voters {
+        start_turn(&voter, input);
+        println!("Who to put on trial?");
+        print_options(&options.iter().map(|o| &o.1).collect::<Vec<_>>());
+        if let Some(choices) = input.read_choices(options.len(), allow_multiple) {
+            let choices = choices.iter().map(|c| *c as i32).collect();
+            if let Err(err) = game.add_vote(voter.player_id, choices) {
+                println!("{err}");
+            }
+        }
+    }
+}
+
+fn play_trial(game: &mut dyn Game, input: &mut Input) {
+    let (Ok(jury), Ok(options)) = (game.get_jury(), game.get_verdict_options()) else {
+        return;
+    };
+    let jury = jury.into_iter().cloned().collect::<Vec<_>>();
+
+    for juror in jury {
+        start_turn(&juror, input);
+        println!("Vote on trial:");
+        print_options(&options.iter().map(|v| v.to_string()).collect::<Vec<_>>());
+        if let Some(choices) = input.read_choices(options.len(), false) {
+            if let Err(err) = game.add_verdict(juror.player_id, choices[0] as i32) {
+                println!("{err}");
+            }
+        }
+    }
+}
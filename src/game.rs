@@ -1,56 +1,301 @@
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt};
-use teloxide::types::{ChatId, MessageId};
 
 use crate::lobby_manager::Lobby;
+use game_setup::GameSetup;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// Identifies a player independently of the frontend they play through, e.g. a Telegram chat id
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, derive_more::Display, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PlayerId(pub i64);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Role {
     Mafia,
     Civilian,
+    Doctor,
+    Detective,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Player {
-    pub chat_id: ChatId,
+    pub player_id: PlayerId,
     pub username: String,
     pub role: Role,
     is_alive: bool,
+    /// Whether the player quit or was replaced by a substitute. Players who left are dead
+    #[serde(default)]
+    has_left: bool,
+}
+
+impl Player {
+    pub fn is_alive(&self) -> bool {
+        self.is_alive
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum GamePhase {
     Night {
         actions: Vec<Action>,
     },
     Voting {
-        poll_id_map: HashMap<ChatId, MessageId>,
-        votes: HashMap<ChatId, Vec<ChatId>>,
+        votes: HashMap<PlayerId, Vec<PlayerId>>,
+    },
+    /// The defendant speaks in their defense before the trial. Only they may talk
+    Defense {
+        defendant_id: PlayerId,
     },
     Trial {
-        defendant_id: ChatId,
-        poll_id_map: HashMap<ChatId, MessageId>,
-        verdicts: HashMap<ChatId, Verdict>,
+        defendant_id: PlayerId,
+        verdicts: HashMap<PlayerId, Verdict>,
+    },
+    /// The player who has just been lynched says their last words. Only they may talk
+    LastWords {
+        player_id: PlayerId,
     },
     Ending,
 }
 
-pub const NOBODY_CHAT_ID: ChatId = ChatId(-1);
+/// An input that changed a game. Replaying a game's events in order rebuilds the game exactly
+#[derive(Clone, Serialize, Deserialize)]
+pub enum GameEvent {
+    /// The game was created with these players, after their roles were dealt using `seed`
+    Started {
+        players: Vec<Player>,
+        setup: GameSetup,
+        seed: u64,
+        #[serde(default)]
+        host_id: Option<PlayerId>,
+    },
+    NightAction {
+        player_id: PlayerId,
+        target_id: PlayerId,
+    },
+    Vote {
+        player_id: PlayerId,
+        option_ids: Vec<i32>,
+    },
+    Verdict {
+        player_id: PlayerId,
+        option_id: i32,
+    },
+    /// The player left the game, dying if they were alive
+    Quit {
+        player_id: PlayerId,
+    },
+    /// The host removed the player from play, killing them
+    Modkilled {
+        player_id: PlayerId,
+    },
+    /// The host paused or resumed the game
+    Paused {
+        paused: bool,
+    },
+    /// A new player took over the player's place, under their own id and username
+    Substituted {
+        player_id: PlayerId,
+        substitute_id: PlayerId,
+        username: String,
+    },
+    /// The phase ended once every player had given their input
+    PhaseEnded,
+    /// The phase was forced to end, e.g. by its deadline
+    PhaseForced,
+}
+
+/// How many ended phases a game remembers
+pub const PHASE_HISTORY_LEN: usize = 8;
+
+/// A phase that has ended, as remembered in the game's history
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PhaseRecord {
+    pub phase_number: usize,
+    /// The phase as it was when it ended, with every input given during it
+    pub phase: GamePhase,
+    /// The transition message announcing how the phase ended
+    pub outcome: String,
+}
+
+/// What happened in an ended phase, kept for the summary shown once the game ends
+#[derive(Clone, Serialize, Deserialize)]
+pub enum TimelineEntry {
+    Night {
+        night: usize,
+        killed: Vec<PlayerId>,
+    },
+    /// Every living player's votes, in player order. Players who did not vote have no targets
+    Voting {
+        day: usize,
+        votes: Vec<(PlayerId, Vec<PlayerId>)>,
+    },
+    /// Every juror's verdict, in player order. Jurors who gave no verdict abstained
+    Trial {
+        day: usize,
+        defendant_id: PlayerId,
+        verdicts: Vec<(PlayerId, Verdict)>,
+        lynched: bool,
+    },
+    /// The player quit during `time`, e.g. "night 2"
+    Quit {
+        player_id: PlayerId,
+        time: String,
+    },
+    /// The host removed the player from play during `time`
+    Modkilled {
+        player_id: PlayerId,
+        time: String,
+    },
+    /// The player, then named `username`, was replaced by the substitute
+    Substituted {
+        player_id: PlayerId,
+        username: String,
+        substitute_id: PlayerId,
+    },
+}
+
+pub const NOBODY_PLAYER_ID: PlayerId = PlayerId(-1);
 pub const NOBODY_USERNAME: &str = "Nobody";
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Action {
-    Kill { source: ChatId, target: ChatId },
+    Kill { source: PlayerId, target: PlayerId },
+    Protect { source: PlayerId, target: PlayerId },
+    Investigate { source: PlayerId, target: PlayerId },
 }
 
-pub const VOTE_OPTION_NOBODY: ChatId = ChatId(-1);
+impl Action {
+    /// Returns the player_id of the player performing the action
+    pub fn source(&self) -> PlayerId {
+        match self {
+            Action::Kill { source, .. }
+            | Action::Protect { source, .. }
+            | Action::Investigate { source, .. } => *source,
+        }
+    }
+
+    /// Returns the player_id of the player the action targets
+    pub fn target(&self) -> PlayerId {
+        match self {
+            Action::Kill { target, .. }
+            | Action::Protect { target, .. }
+            | Action::Investigate { target, .. } => *target,
+        }
+    }
+}
 
+/// How the Mafia decide on the one player they kill each night
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TeamKillMode {
+    /// Every Mafia picks a victim and the most popular pick is killed. If the top picks are tied,
+    /// nobody is killed
+    Majority,
+    /// The first living Mafia picks the victim, while the other Mafia can only suggest one
+    DesignatedKiller,
+}
+
+/// The result of a Detective's investigation on a given night
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Investigation {
+    pub night: usize,
+    pub investigator_id: PlayerId,
+    pub target_username: String,
+    pub is_mafia: bool,
+}
+
+impl fmt::Display for Investigation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_mafia {
+            write!(
+                f,
+                "Night {}: {} is a Mafia",
+                self.night, self.target_username
+            )
+        } else {
+            write!(
+                f,
+                "Night {}: {} is not a Mafia",
+                self.night, self.target_username
+            )
+        }
+    }
+}
+
+/// The channels that chat messages can be relayed through
 #[derive(Clone, Copy)]
+pub enum ChatChannel {
+    /// Living players talking during the day. Dead players can read but not reply
+    Day,
+    /// Mafia talking among themselves at night
+    Mafia,
+    /// Dead players talking among themselves
+    Graveyard,
+}
+
+/// What should happen to a chat message sent by a player
+pub enum ChatRoute {
+    /// Relay the message to these players
+    Relay {
+        channel: ChatChannel,
+        recipients: Vec<PlayerId>,
+    },
+    /// Do not relay the message, replying to the sender with the reason instead
+    Reject { reason: String },
+}
+
+pub const VOTE_OPTION_NOBODY: PlayerId = PlayerId(-1);
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Verdict {
     Guilty,
     Innocent,
     Abstain,
 }
 
+/// Why a game rejected an input, e.g. a late poll answer or a stale button press
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameError {
+    /// The input or query does not belong to the game's current phase
+    WrongPhase,
+    /// The chosen target or option is not one of the options offered to the player
+    InvalidTarget,
+    /// The player chose more than one option where only one may be chosen
+    TooManyChoices,
+    /// The player is dead, or is not in the game
+    NotAlive,
+    /// The player has no say in the current phase, e.g. a defendant voting on their own trial
+    NotAllowed,
+    /// The player has already given their input for the current phase
+    AlreadyActed,
+    /// The host has paused the game, so no inputs are taken until it is resumed
+    Paused,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Mafia => write!(f, "Mafia"),
+            Role::Civilian => write!(f, "Civilian"),
+            Role::Doctor => write!(f, "Doctor"),
+            Role::Detective => write!(f, "Detective"),
+        }
+    }
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameError::WrongPhase => write!(f, "This choice has expired"),
+            GameError::InvalidTarget => write!(f, "That is not one of your options"),
+            GameError::TooManyChoices => write!(f, "You can only choose one option"),
+            GameError::NotAlive => write!(f, "Only living players can do that"),
+            GameError::NotAllowed => write!(f, "You do not get a choice right now"),
+            GameError::AlreadyActed => write!(f, "You have already made your choice"),
+            GameError::Paused => write!(f, "The game is paused"),
+        }
+    }
+}
+
 impl fmt::Display for Verdict {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -62,60 +307,168 @@ impl fmt::Display for Verdict {
 }
 
 pub trait Game: Send + Sync {
-    /// Creates a Game instace from a lobby
-    fn from_lobby(lobby: &Lobby) -> Self
+    /// Creates a Game instace from a lobby. The seed decides everything random about the game, so
+    /// the same lobby, seed and inputs always play out the same game
+    fn from_lobby(lobby: &Lobby, seed: u64) -> Self
+    where
+        Self: Sized;
+
+    /// Restores a Game instance from the JSON produced by `Game::to_json`. Fails if the JSON was
+    /// written in an older format
+    fn from_json(json: &str) -> serde_json::Result<Self>
+    where
+        Self: Sized;
+
+    /// Rebuilds a game by applying its events in order, starting from `GameEvent::Started`
+    fn replay(events: &[GameEvent]) -> Result<Self, String>
     where
         Self: Sized;
 
     fn snapshot(&self) -> Box<dyn Game>;
 
+    /// Serializes the game's state into versioned JSON, see `serialization::GAME_JSON_VERSION`
+    fn to_json(&self) -> serde_json::Result<String>;
+
+    /// Returns the setup the game was created with
+    fn get_setup(&self) -> &GameSetup;
+
+    /// Returns the seed the game was created with
+    fn get_seed(&self) -> u64;
+
+    /// Returns the player who hosts the game and may moderate it. The host starts out as the
+    /// lobby's host, and passes to another player if they leave
+    fn get_host_id(&self) -> Option<PlayerId>;
+
+    fn is_paused(&self) -> bool;
+
+    /// Pauses or resumes the game. While paused, the game rejects every input with
+    /// `GameError::Paused` and its phase does not end on its own. Fails if the game has ended
+    fn set_paused(&mut self, paused: bool) -> Result<(), GameError>;
+
+    /// Returns the players still in the game, living or dead. Players who quit or were replaced
+    /// by a substitute are left out
     fn get_players(&self) -> Vec<&Player>;
 
     fn get_phase(&self) -> &GamePhase;
 
+    /// Returns the number of phases that have ended so far. Used to tell apart repeated phases,
+    /// e.g. a re-vote following a tied vote
+    fn get_phase_number(&self) -> usize;
+
+    /// Returns up to `PHASE_HISTORY_LEN` of the most recently ended phases, oldest first
+    fn get_history(&self) -> Vec<&PhaseRecord>;
+
+    /// Removes the player from the game. A living player dies, and any input given for or
+    /// against them in the current phase is dropped, so that the players who chose them can
+    /// choose again. If the player was the host, hosting passes to the first living player.
+    /// Returns Some(GamePhase) if the quit ended the phase, e.g. by deciding the winner
+    fn quit(&mut self, player_id: PlayerId) -> Result<Option<&GamePhase>, GameError>;
+
+    /// Kills a living player on the host's behalf, e.g. one who stopped playing. Like `quit`, the
+    /// inputs given for or against them are dropped, and Some(GamePhase) is returned if this
+    /// ended the phase
+    fn modkill(&mut self, player_id: PlayerId) -> Result<Option<&GamePhase>, GameError>;
+
+    /// Hands a living player's place, with their role and inputs, to a substitute who is not in
+    /// the game yet. The player leaves the game
+    fn substitute_player(
+        &mut self,
+        player_id: PlayerId,
+        substitute_id: PlayerId,
+        username: String,
+    ) -> Result<(), GameError>;
+
+    /// Returns a summary of the whole game so far: every player's role and fate, every voting
+    /// round's votes and how many jurors gave each verdict in every trial
+    fn get_summary(&self) -> String;
+
+    /// Returns the events recorded since the last call, oldest first, e.g. for storage to append
+    fn take_events(&mut self) -> Vec<GameEvent>;
+
+    /// Applies an event to the game as if its input was given again, recording it like the
+    /// original input was. Lets a game be replayed step by step
+    fn apply_event(&mut self, event: &GameEvent) -> Result<(), String>;
+
+    /// Returns None if there are no winners, and Some(String) if there is a winner,
+    /// where String is the faction of the winner
+    fn get_winner(&self) -> Option<String>;
+
+    /// Returns the players that the current phase is still waiting on for an input
+    fn get_idle_players(&self) -> Vec<&Player>;
+
     /// Attempts to end the phase. Returns Some(GamePhase) if the phase ended. \
-    /// `GamePhase::Night` should always return `None`
+    /// The phase only ends once every player has given their input. Phases that take no input,
+    /// e.g. GamePhase::Defense, only end when forced
     fn end_phase(&mut self) -> Option<&GamePhase>;
 
+    /// Ends the phase with whatever inputs have been given so far, e.g. when the phase's deadline
+    /// has passed or the host moves the game on. Returns `None` if the game has already ended
+    fn force_end_phase(&mut self) -> Option<&GamePhase>;
+
+    /// Ends the phase, forcing it to end if some players skipped their input, as if the phase's
+    /// deadline had passed. Used where phases are played one after another without deadlines,
+    /// e.g. in the terminal frontend and the simulator
+    fn end_or_force_phase(&mut self) -> Option<&GamePhase> {
+        if self.end_phase().is_none() {
+            return self.force_end_phase();
+        }
+        Some(self.get_phase())
+    }
+
     /// Returns the most recent transition message
     fn get_transition_message(&self) -> String;
 
-    /// Returns a mapping from `chat_id` to `(message: String, options: Vec<(target_id: ChatId, username: String)>)`
+    /// Returns the messages from the most recent transition that should only be seen by
+    /// specific players, as a mapping from `player_id` to the messages for that player
+    fn get_private_messages(&self) -> HashMap<PlayerId, Vec<String>>;
+
+    /// Returns every investigation made by the player so far, ordered by night
+    fn get_investigations(&self, investigator_id: PlayerId) -> Vec<&Investigation>;
+
+    /// Decides who a chat message from the sender should be relayed to
+    fn route_chat_message(&self, sender_id: PlayerId) -> ChatRoute;
+
+    /// Returns a mapping from `player_id` to `(message: String, options: Vec<(target_id: PlayerId, username: String)>)`
     ///
     /// The info is used to display an options box for the user. \
     /// The options vector will be either length 0 or >= 2. If zero, then no options will be displayed
-    fn get_night_actions(&self) -> HashMap<ChatId, (String, Vec<(ChatId, String)>)>;
+    fn get_night_actions(&self) -> HashMap<PlayerId, (String, Vec<(PlayerId, String)>)>;
 
-    /// Panics if the game is not in GamePhase::Night
-    fn add_night_action(&mut self, actor_id: ChatId, target_id: ChatId);
+    /// Returns the living Mafia and a summary of their current kill picks, so that the summary can
+    /// be shown to them whenever a pick changes. Fails if the game is not in GamePhase::Night
+    fn get_team_picks(&self) -> Result<(Vec<PlayerId>, String), GameError>;
 
-    /// Panics if the game is not in GamePhase::Voting.
-    /// Returns a iterator over ChatIds and the corresponding display names.
-    fn get_vote_options(&self) -> Vec<(ChatId, String)>;
+    /// Fails if the game is not in GamePhase::Night, or the target is not one of the actor's
+    /// options. Replaces the actor's earlier action, if any
+    fn add_night_action(
+        &mut self,
+        actor_id: PlayerId,
+        target_id: PlayerId,
+    ) -> Result<(), GameError>;
 
-    /// Panics if the game is not in GamePhase::Voting
-    fn get_voters(&self) -> Vec<&Player>;
+    /// Fails if the game is not in GamePhase::Voting.
+    /// Returns the PlayerIds that can be voted for and the corresponding display names.
+    fn get_vote_options(&self) -> Result<Vec<(PlayerId, String)>, GameError>;
 
-    /// Panics if the game is not in GamePhase::Voting. The chosen vector should contain the index of the options
+    /// Fails if the game is not in GamePhase::Voting
+    fn get_voters(&self) -> Result<Vec<&Player>, GameError>;
+
+    /// Fails if the game is not in GamePhase::Voting. The chosen vector should contain the index of the options
     /// as they appear in `get_vote_options`.
-    fn add_vote(&mut self, voter_id: ChatId, choices: Vec<i32>);
+    fn add_vote(&mut self, voter_id: PlayerId, choices: Vec<i32>) -> Result<(), GameError>;
 
-    /// Panics if the game is not in GamePhase::Trial
-    fn get_verdict_options(&self) -> Vec<Verdict>;
+    /// Fails if the game is not in GamePhase::Trial
+    fn get_verdict_options(&self) -> Result<Vec<Verdict>, GameError>;
 
-    /// Panics if the game is not in GamePhase::Trial
-    fn get_jury(&self) -> Vec<&Player>;
+    /// Fails if the game is not in GamePhase::Trial
+    fn get_jury(&self) -> Result<Vec<&Player>, GameError>;
 
-    /// Panics if the game is not in GamePhase::Trial.\
+    /// Fails if the game is not in GamePhase::Trial.\
     /// The chosen index should correspond to the entry in `get_verdict_options`
-    fn add_verdict(&mut self, juror_id: ChatId, chosen: i32);
-
-    /// Panics if the game is not in GamePhase::Voting or GamePhase::Trial.
-    /// The poll_msg_ids HashMap should map the voter's chat id to the message id of the poll.
-    fn add_poll_msg_ids(&mut self, poll_msg_ids: HashMap<ChatId, MessageId>);
-
-    /// Panics if the game is not in GamePhase::Voting or GamePhase::Trial.
-    fn get_poll_msg_ids(&self) -> HashMap<ChatId, MessageId>;
+    fn add_verdict(&mut self, juror_id: PlayerId, chosen: i32) -> Result<(), GameError>;
 }
 
+pub mod game_setup;
 pub mod game_v1;
+pub mod night_resolution;
+pub mod serialization;
@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+use super::*;
+
+/// The phase that a game starts in
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StartPhase {
+    Night,
+    Day,
+}
+
+/// The rules a game is created with. Edited by the host while the game is still a lobby
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameSetup {
+    /// How many players get each role. Players without a role become Civilians. If `None`, the
+    /// roles are taken from the preset for the number of players, see `ROLE_PRESETS`
+    pub role_counts: Option<BTreeMap<Role, usize>>,
+    /// Whether a player's role is announced when they die
+    pub reveal_role_on_death: bool,
+    pub start_phase: StartPhase,
+    /// Whether players may vote for more than one player to put on trial
+    pub allow_multiple_votes: bool,
+    /// Whether "Nobody" is a voting option
+    pub allow_no_lynch: bool,
+    pub team_kill_mode: TeamKillMode,
+    /// Whether the defendant gets to speak in their defense before the trial
+    #[serde(default)]
+    pub allow_defense: bool,
+    /// Whether a lynched player gets to say their last words before the night
+    #[serde(default)]
+    pub allow_last_words: bool,
+}
+
+impl Default for GameSetup {
+    fn default() -> Self {
+        GameSetup {
+            role_counts: None,
+            reveal_role_on_death: false,
+            start_phase: StartPhase::Night,
+            allow_multiple_votes: true,
+            allow_no_lynch: true,
+            team_kill_mode: TeamKillMode::Majority,
+            allow_defense: false,
+            allow_last_words: false,
+        }
+    }
+}
+
+/// A balanced role list for lobbies of at least `min_players` players
+struct RolePreset {
+    min_players: usize,
+    roles: &'static [(Role, usize)],
+}
+
+/// The role lists used when the host has not chosen any, ordered by `min_players`. Roughly 1 in
+/// 4 players is a Mafia, with the special roles unlocked as the lobby grows
+const ROLE_PRESETS: &[RolePreset] = &[
+    RolePreset {
+        min_players: 0,
+        roles: &[(Role::Mafia, 1)],
+    },
+    RolePreset {
+        min_players: 4,
+        roles: &[(Role::Mafia, 1), (Role::Doctor, 1)],
+    },
+    RolePreset {
+        min_players: 5,
+        roles: &[(Role::Mafia, 1), (Role::Doctor, 1), (Role::Detective, 1)],
+    },
+    RolePreset {
+        min_players: 8,
+        roles: &[(Role::Mafia, 2), (Role::Doctor, 1), (Role::Detective, 1)],
+    },
+    RolePreset {
+        min_players: 12,
+        roles: &[(Role::Mafia, 3), (Role::Doctor, 1), (Role::Detective, 1)],
+    },
+    RolePreset {
+        min_players: 16,
+        roles: &[(Role::Mafia, 4), (Role::Doctor, 2), (Role::Detective, 1)],
+    },
+    RolePreset {
+        min_players: 20,
+        roles: &[(Role::Mafia, 5), (Role::Doctor, 2), (Role::Detective, 2)],
+    },
+];
+
+/// Returns the preset role list for a lobby of `player_count` players
+fn preset_role_counts(player_count: usize) -> BTreeMap<Role, usize> {
+    ROLE_PRESETS
+        .iter()
+        .rev()
+        .find(|preset| preset.min_players <= player_count)
+        .map(|preset| preset.roles.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+fn parse_role(text: &str) -> Option<Role> {
+    match text.to_lowercase().as_str() {
+        "mafia" => Some(Role::Mafia),
+        "doctor" => Some(Role::Doctor),
+        "detective" => Some(Role::Detective),
+        _ => None,
+    }
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("Expected \"on\" or \"off\", got \"{value}\"")),
+    }
+}
+
+fn format_switch(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl GameSetup {
+    /// Returns how many players get each role. Roles that nobody gets are left out
+    pub fn get_role_counts(&self, player_count: usize) -> BTreeMap<Role, usize> {
+        let mut role_counts = self
+            .role_counts
+            .clone()
+            .unwrap_or_else(|| preset_role_counts(player_count));
+        role_counts.retain(|_, count| *count > 0);
+        role_counts
+    }
+
+    /// Returns the role of every player, with Civilians making up the remaining players
+    pub fn get_roles(&self, player_count: usize) -> Vec<Role> {
+        let mut roles = self
+            .get_role_counts(player_count)
+            .into_iter()
+            .flat_map(|(role, count)| vec![role; count])
+            .collect::<Vec<_>>();
+        roles.resize(player_count, Role::Civilian);
+        roles
+    }
+
+    /// Checks that a game can be played with this setup and number of players
+    pub fn validate(&self, player_count: usize) -> Result<(), String> {
+        let role_counts = self.get_role_counts(player_count);
+        let mafia_count = role_counts.get(&Role::Mafia).copied().unwrap_or(0);
+        let role_total = role_counts
+            .values()
+            .fold(0_usize, |total, count| total.saturating_add(*count));
+
+        if mafia_count == 0 {
+            Err(String::from("The game needs at least 1 Mafia"))
+        } else if role_total > player_count {
+            Err(format!(
+                "The setup has {role_total} roles, but there are only {player_count} players"
+            ))
+        } else if mafia_count >= player_count - mafia_count {
+            Err(format!(
+                "{mafia_count} Mafia would win straight away against {} other players",
+                player_count - mafia_count
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Changes one option of the setup, as given by the host. Role counts start from the
+    /// preset roles for `player_count` players, and cannot be more than `player_count`
+    pub fn set_option(
+        &mut self,
+        option: &str,
+        value: &str,
+        player_count: usize,
+    ) -> Result<(), String> {
+        match option.to_lowercase().as_str() {
+            "roles" => {
+                if value.to_lowercase() != "auto" {
+                    return Err(format!(
+                        "\"roles\" only accepts \"auto\", got \"{value}\". Set a role's count \
+                        with /set <role> <count>"
+                    ));
+                }
+                self.role_counts = None;
+            }
+            "reveal" => self.reveal_role_on_death = parse_switch(value)?,
+            "start" => {
+                self.start_phase = match value.to_lowercase().as_str() {
+                    "night" => StartPhase::Night,
+                    "day" => StartPhase::Day,
+                    _ => return Err(format!("Expected \"day\" or \"night\", got \"{value}\"")),
+                }
+            }
+            "multivote" => self.allow_multiple_votes = parse_switch(value)?,
+            "nolynch" => self.allow_no_lynch = parse_switch(value)?,
+            "defense" => self.allow_defense = parse_switch(value)?,
+            "lastwords" => self.allow_last_words = parse_switch(value)?,
+            "killmode" => {
+                self.team_kill_mode = match value.to_lowercase().as_str() {
+                    "majority" => TeamKillMode::Majority,
+                    "killer" => TeamKillMode::DesignatedKiller,
+                    _ => {
+                        return Err(format!(
+                            "Expected \"majority\" or \"killer\", got \"{value}\""
+                        ))
+                    }
+                }
+            }
+            role_text => {
+                let Some(role) = parse_role(role_text) else {
+                    return Err(format!("Unknown option \"{option}\""));
+                };
+                let Ok(count) = value.parse::<usize>() else {
+                    return Err(format!("Expected a number of players, got \"{value}\""));
+                };
+                if count > player_count {
+                    return Err(format!(
+                        "Cannot give {count} players a role when there are only {player_count} \
+                        players"
+                    ));
+                }
+
+                let mut role_counts = self.get_role_counts(player_count);
+                role_counts.insert(role, count);
+                self.role_counts = Some(role_counts);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the name a player's death is announced with
+    pub fn get_death_name(&self, player: &Player) -> String {
+        if self.reveal_role_on_death {
+            format!("{} ({})", player.username, player.role)
+        } else {
+            player.username.clone()
+        }
+    }
+
+    /// Describes the options that can be passed to `GameSetup::set_option`
+    pub fn describe_options() -> &'static str {
+        "/set mafia|doctor|detective <count> - Number of players with the role\n\
+        /set roles auto - Use the preset roles for the number of players\n\
+        /set reveal on|off - Reveal a player's role when they die\n\
+        /set start night|day - Phase the game starts in\n\
+        /set multivote on|off - Allow voting for more than one player\n\
+        /set nolynch on|off - Allow voting for nobody\n\
+        /set defense on|off - Let the defendant speak in their defense before the trial\n\
+        /set lastwords on|off - Let a lynched player say their last words\n\
+        /set killmode majority|killer - Whether the Mafia's kill is chosen by majority, \
+        or by one designated killer"
+    }
+
+    /// Describes the roles for a lobby of `player_count` players, and where they come from
+    pub fn describe_roles(&self, player_count: usize) -> String {
+        let roles = self
+            .get_role_counts(player_count)
+            .iter()
+            .map(|(role, count)| format!("{count} {role}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match self.role_counts {
+            Some(_) => format!("Roles: {roles} (chosen by host)"),
+            None => format!("Roles: {roles} (preset for {player_count} players)"),
+        }
+    }
+
+    /// Describes the setup for a lobby of `player_count` players
+    pub fn describe(&self, player_count: usize) -> String {
+        format!(
+            "{}\n\
+            Reveal roles on death: {}\n\
+            Starts at: {}\n\
+            Multiple votes: {}\n\
+            No-lynch vote: {}\n\
+            Defense: {}\n\
+            Last words: {}\n\
+            Mafia kill: {}",
+            self.describe_roles(player_count),
+            format_switch(self.reveal_role_on_death),
+            self.start_phase,
+            format_switch(self.allow_multiple_votes),
+            format_switch(self.allow_no_lynch),
+            format_switch(self.allow_defense),
+            format_switch(self.allow_last_words),
+            self.team_kill_mode,
+        )
+    }
+}
+
+impl fmt::Display for StartPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartPhase::Night => write!(f, "night"),
+            StartPhase::Day => write!(f, "day"),
+        }
+    }
+}
+
+impl fmt::Display for TeamKillMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TeamKillMode::Majority => write!(f, "majority"),
+            TeamKillMode::DesignatedKiller => write!(f, "designated killer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_preset_is_playable_from_its_player_count() {
+        let setup = GameSetup::default();
+        for (i, preset) in ROLE_PRESETS.iter().enumerate() {
+            let roles = preset.roles.iter().copied().collect::<BTreeMap<_, _>>();
+            // The smallest game is played by 3 players
+            let player_count = preset.min_players.max(3);
+            assert!(
+                setup.validate(player_count).is_ok(),
+                "{player_count} players"
+            );
+            assert_eq!(setup.get_role_counts(player_count), roles);
+
+            // The preset is used until the next one takes over
+            if let Some(next) = ROLE_PRESETS.get(i + 1) {
+                assert_eq!(setup.get_role_counts(next.min_players - 1), roles);
+                assert_ne!(setup.get_role_counts(next.min_players), roles);
+            }
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unplayable_setups() {
+        let mut setup = GameSetup::default();
+        assert!(setup.validate(5).is_ok());
+
+        setup.set_option("mafia", "0", 5).unwrap();
+        assert_eq!(
+            setup.validate(5),
+            Err(String::from("The game needs at least 1 Mafia"))
+        );
+
+        setup.set_option("mafia", "2", 5).unwrap();
+        setup.set_option("doctor", "2", 5).unwrap();
+        setup.set_option("detective", "2", 5).unwrap();
+        assert_eq!(
+            setup.validate(5),
+            Err(String::from(
+                "The setup has 6 roles, but there are only 5 players"
+            ))
+        );
+
+        // Counts too large to add up are still rejected
+        setup.role_counts = Some(BTreeMap::from([
+            (Role::Mafia, 1),
+            (Role::Doctor, usize::MAX),
+        ]));
+        assert_eq!(
+            setup.validate(5),
+            Err(format!(
+                "The setup has {} roles, but there are only 5 players",
+                usize::MAX
+            ))
+        );
+
+        setup.set_option("roles", "auto", 5).unwrap();
+        setup.set_option("mafia", "3", 6).unwrap();
+        assert_eq!(
+            setup.validate(6),
+            Err(String::from(
+                "3 Mafia would win straight away against 3 other players"
+            ))
+        );
+    }
+
+    #[test]
+    fn set_option_changes_the_setup() {
+        let mut setup = GameSetup::default();
+        for (option, value) in [
+            ("reveal", "on"),
+            ("START", "Day"),
+            ("multivote", "off"),
+            ("nolynch", "off"),
+            ("killmode", "killer"),
+            ("defense", "on"),
+            ("lastwords", "on"),
+            ("doctor", "0"),
+        ] {
+            setup.set_option(option, value, 6).unwrap();
+        }
+
+        assert!(setup.reveal_role_on_death);
+        assert!(setup.start_phase == StartPhase::Day);
+        assert!(!setup.allow_multiple_votes);
+        assert!(!setup.allow_no_lynch);
+        assert!(setup.team_kill_mode == TeamKillMode::DesignatedKiller);
+        assert!(setup.allow_defense);
+        assert!(setup.allow_last_words);
+        // The other roles are kept from the preset the counts started from
+        assert_eq!(
+            setup.get_role_counts(6),
+            BTreeMap::from([(Role::Mafia, 1), (Role::Detective, 1)])
+        );
+
+        setup.set_option("roles", "auto", 6).unwrap();
+        assert!(setup.role_counts.is_none());
+    }
+
+    #[test]
+    fn set_option_rejects_bad_options_and_values() {
+        let mut setup = GameSetup::default();
+        let errors = [
+            ("civilian", "2", "Unknown option \"civilian\""),
+            ("speed", "fast", "Unknown option \"speed\""),
+            (
+                "roles",
+                "3",
+                "\"roles\" only accepts \"auto\", got \"3\". Set a role's count with /set \
+                <role> <count>",
+            ),
+            ("reveal", "yes", "Expected \"on\" or \"off\", got \"yes\""),
+            (
+                "start",
+                "dawn",
+                "Expected \"day\" or \"night\", got \"dawn\"",
+            ),
+            (
+                "killmode",
+                "vote",
+                "Expected \"majority\" or \"killer\", got \"vote\"",
+            ),
+            ("mafia", "-1", "Expected a number of players, got \"-1\""),
+            (
+                "mafia",
+                "18446744073709551615",
+                "Cannot give 18446744073709551615 players a role when there are only 5 players",
+            ),
+        ];
+        for (option, value, error) in errors {
+            assert_eq!(
+                setup.set_option(option, value, 5),
+                Err(String::from(error)),
+                "/set {option} {value}"
+            );
+        }
+        assert!(setup.role_counts.is_none());
+    }
+}
@@ -1,62 +1,278 @@
-use rand::{seq::SliceRandom, thread_rng};
-use std::collections::{HashMap, HashSet};
-use teloxide::types::{ChatId, MessageId};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use super::*;
-use crate::game::{Game, GamePhase, Player};
+use crate::game::{
+    game_setup::{GameSetup, StartPhase},
+    night_resolution::{
+        consolidate_team_kill, get_designated_killer, get_team_kill, resolve_night,
+    },
+    serialization::{from_versioned_json, to_versioned_json},
+    Game, GamePhase, Player,
+};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GameV1 {
     pub players: Vec<Player>,
     pub phase: GamePhase,
-    previous: Option<Box<GameV1>>,
+    #[serde(default)]
+    history: VecDeque<PhaseRecord>,
+    #[serde(default)]
+    timeline: Vec<TimelineEntry>,
     transition_message: String,
+    private_messages: HashMap<PlayerId, Vec<String>>,
+    night: usize,
+    investigations: Vec<Investigation>,
+    phase_number: usize,
+    /// Games saved before setups could be configured were played with the default setup
+    #[serde(default)]
+    setup: GameSetup,
+    #[serde(default)]
+    seed: u64,
+    /// Games saved before hosts carried over from the lobby are hosted by their first player
+    #[serde(default)]
+    host_id: Option<PlayerId>,
+    #[serde(default)]
+    is_paused: bool,
+    /// Events recorded since they were last taken by `take_events`
+    #[serde(skip)]
+    unsaved_events: Vec<GameEvent>,
 }
 
 impl GameV1 {
-    fn should_end_night(&self) -> bool {
-        if let GamePhase::Night { actions, .. } = &self.phase {
-            let check_player_is_idle = |p: &&Player| match p.role {
-                Role::Mafia => actions
+    /// Creates a game whose roles have already been dealt, recording its `GameEvent::Started`
+    fn start(
+        players: Vec<Player>,
+        setup: GameSetup,
+        seed: u64,
+        host_id: Option<PlayerId>,
+    ) -> GameV1 {
+        GameV1 {
+            players: players.clone(),
+            phase: match setup.start_phase {
+                StartPhase::Night => GamePhase::Night {
+                    actions: Vec::new(),
+                },
+                StartPhase::Day => GamePhase::Voting {
+                    votes: HashMap::new(),
+                },
+            },
+            history: VecDeque::new(),
+            timeline: Vec::new(),
+            transition_message: String::from("Welcome to the Mafia Game"),
+            private_messages: HashMap::new(),
+            night: 1,
+            investigations: Vec::new(),
+            phase_number: 0,
+            setup: setup.clone(),
+            seed,
+            host_id,
+            is_paused: false,
+            unsaved_events: vec![GameEvent::Started {
+                players,
+                setup,
+                seed,
+                host_id,
+            }],
+        }
+    }
+
+    /// Ends the current phase, returning false if the game has already ended
+    fn end_current_phase(&mut self) -> bool {
+        let ended_phase = self.phase.clone();
+        let (night, day) = (self.night, self.get_day());
+        let living_ids = self
+            .players
+            .iter()
+            .filter(|p| p.is_alive)
+            .map(|p| p.player_id)
+            .collect::<Vec<_>>();
+        match &self.phase {
+            GamePhase::Night { .. } => self.end_night(),
+            GamePhase::Voting { .. } => self.end_voting(),
+            GamePhase::Defense { .. } => self.end_defense(),
+            GamePhase::Trial { .. } => self.end_trial(),
+            GamePhase::LastWords { .. } => self.end_last_words(),
+            GamePhase::Ending => return false,
+        }
+
+        let died = living_ids
+            .iter()
+            .filter(|id| self.get_player(**id).is_some_and(|p| !p.is_alive))
+            .copied()
+            .collect::<Vec<_>>();
+        let entry_opt = match &ended_phase {
+            GamePhase::Night { .. } => Some(TimelineEntry::Night {
+                night,
+                killed: died,
+            }),
+            GamePhase::Voting { votes } => Some(TimelineEntry::Voting {
+                day,
+                votes: living_ids
                     .iter()
-                    .find(|a| match a {
-                        Action::Kill { source, .. } if source == &p.chat_id => true,
-                        _ => false,
-                    })
-                    .is_none(),
-                Role::Civilian => false,
-            };
+                    .map(|id| (*id, votes.get(id).cloned().unwrap_or_default()))
+                    .collect(),
+            }),
+            GamePhase::Trial {
+                defendant_id,
+                verdicts,
+            } => Some(TimelineEntry::Trial {
+                day,
+                defendant_id: *defendant_id,
+                verdicts: living_ids
+                    .iter()
+                    .filter(|id| *id != defendant_id)
+                    .map(|id| (*id, verdicts.get(id).copied().unwrap_or(Verdict::Abstain)))
+                    .collect(),
+                lynched: died.contains(defendant_id),
+            }),
+            // Nothing is decided while players speak
+            GamePhase::Defense { .. } | GamePhase::LastWords { .. } => None,
+            GamePhase::Ending => unreachable!(),
+        };
+        self.timeline.extend(entry_opt);
 
-            let idle_players = self.players.iter().filter(check_player_is_idle);
+        self.record_ended_phase(ended_phase);
+        true
+    }
 
-            idle_players.count() == 0
-        } else {
-            panic!("should_end_night called when not in GamePhase::Night")
+    /// Remembers the phase that has just ended, along with the current transition message
+    fn record_ended_phase(&mut self, ended_phase: GamePhase) {
+        if self.history.len() == PHASE_HISTORY_LEN {
+            self.history.pop_front();
         }
+        self.history.push_back(PhaseRecord {
+            phase_number: self.phase_number,
+            phase: ended_phase,
+            outcome: self.transition_message.clone(),
+        });
+        self.phase_number += 1;
     }
 
-    /// Attempts to end the night, returning the new, current phase if successful
-    fn end_night(&mut self) -> Option<&GamePhase> {
-        if !self.should_end_night() {
-            return None;
+    /// Drops every input given by or against a player who has just died, ending the phase early
+    /// if it cannot go on without them. Returns true if the phase ended
+    fn drop_inputs_of(&mut self, player_id: PlayerId) -> bool {
+        let ended_phase = self.phase.clone();
+        match &mut self.phase {
+            GamePhase::Night { actions } => {
+                actions.retain(|a| a.source() != player_id && a.target() != player_id)
+            }
+            GamePhase::Voting { votes } => {
+                votes.remove(&player_id);
+                for targets in votes.values_mut() {
+                    targets.retain(|target_id| *target_id != player_id);
+                }
+                // Voters whose only choice was the player vote again
+                votes.retain(|_, targets| !targets.is_empty());
+            }
+            GamePhase::Trial { verdicts, .. } => {
+                verdicts.remove(&player_id);
+            }
+            GamePhase::Defense { .. } | GamePhase::LastWords { .. } | GamePhase::Ending => {}
         }
 
-        self.previous = Some(Box::new(self.clone()));
+        if let Some(winning_faction) = self.get_winner() {
+            self.transition_message = format!("{} won the game!", winning_faction);
+            self.phase = GamePhase::Ending;
+        } else if matches!(
+            self.phase,
+            GamePhase::Defense { defendant_id } | GamePhase::Trial { defendant_id, .. }
+                if defendant_id == player_id
+        ) {
+            self.transition_message = String::from(
+                "The defendant is gone, so the trial is over. Moving to night time...",
+            );
+            self.phase = GamePhase::Night {
+                actions: Vec::new(),
+            };
+        } else {
+            return false;
+        }
 
-        if let GamePhase::Night { actions, .. } = &mut self.phase {
-            // Resolve actions
-            let mut killed_usernames = Vec::new();
-            for a in actions {
-                match a {
-                    Action::Kill { target, .. } => {
-                        if let Some(target) = self.players.iter_mut().find(|p| p.chat_id == *target)
-                        {
-                            target.is_alive = false;
-                            killed_usernames.push(target.username.clone());
+        self.private_messages.clear();
+        self.record_ended_phase(ended_phase);
+        true
+    }
+
+    /// Replaces the player's id with the substitute's wherever the phase refers to it
+    fn replace_in_phase(phase: &mut GamePhase, player_id: PlayerId, substitute_id: PlayerId) {
+        let replace = |id: &mut PlayerId| {
+            if *id == player_id {
+                *id = substitute_id;
+            }
+        };
+        match phase {
+            GamePhase::Night { actions } => {
+                for action in actions.iter_mut() {
+                    match action {
+                        Action::Kill { source, target }
+                        | Action::Protect { source, target }
+                        | Action::Investigate { source, target } => {
+                            replace(source);
+                            replace(target);
                         }
                     }
                 }
             }
+            GamePhase::Voting { votes } => {
+                if let Some(targets) = votes.remove(&player_id) {
+                    votes.insert(substitute_id, targets);
+                }
+                for targets in votes.values_mut() {
+                    targets.iter_mut().for_each(replace);
+                }
+            }
+            GamePhase::Defense { defendant_id } => replace(defendant_id),
+            GamePhase::Trial {
+                defendant_id,
+                verdicts,
+            } => {
+                replace(defendant_id);
+                if let Some(verdict) = verdicts.remove(&player_id) {
+                    verdicts.insert(substitute_id, verdict);
+                }
+            }
+            GamePhase::LastWords { player_id: id } => replace(id),
+            GamePhase::Ending => {}
+        }
+    }
+
+    /// Describes the current phase for the summary, e.g. "night 2"
+    fn get_time(&self) -> String {
+        match self.phase {
+            GamePhase::Night { .. } => format!("night {}", self.night),
+            _ => format!("day {}", self.get_day()),
+        }
+    }
+
+    /// Resolves the night's actions and moves to the next phase
+    fn end_night(&mut self) {
+        if let GamePhase::Night { actions, .. } = &self.phase {
+            let actions = consolidate_team_kill(&self.players, actions, self.setup.team_kill_mode);
+            let outcome = resolve_night(&self.players, &actions, self.night);
+
+            // Apply outcome
+            let mut killed_usernames = Vec::new();
+            for target_id in outcome.killed.iter() {
+                if let Some(target) = self.players.iter_mut().find(|p| p.player_id == *target_id) {
+                    target.is_alive = false;
+                    killed_usernames.push(self.setup.get_death_name(target));
+                }
+            }
+
+            self.private_messages.clear();
+            for investigation in outcome.investigations {
+                self.private_messages
+                    .entry(investigation.investigator_id)
+                    .or_default()
+                    .push(format!("Investigation result - {investigation}"));
+                self.investigations.push(investigation);
+            }
+            for target_id in outcome.killed.iter() {
+                self.welcome_to_graveyard(*target_id);
+            }
+            self.night += 1;
 
             // Update state
             (self.transition_message, self.phase) = if let Some(winning_faction) = self.get_winner()
@@ -66,47 +282,32 @@ impl GameV1 {
                     GamePhase::Ending,
                 )
             } else {
+                let mut message = if !killed_usernames.is_empty() {
+                    format!("{} died last night", killed_usernames.join(", "))
+                } else {
+                    String::from("Nobody died last night")
+                };
+                if !outcome.saved.is_empty() {
+                    message.push_str("\nAn attack was prevented last night");
+                }
+
                 (
-                    if killed_usernames.len() > 0 {
-                        format!("{} died last night", killed_usernames.join(", "))
-                    } else {
-                        format!("Nobody died last night")
-                    },
+                    message,
                     GamePhase::Voting {
                         votes: HashMap::new(),
-                        poll_id_map: HashMap::new(),
                     },
                 )
             };
-
-            Some(&self.phase)
         } else {
             panic!("end_night called when not in GamePhase::Night")
         }
     }
 
-    fn should_end_voting(&self) -> bool {
+    /// Tallies the votes and moves to the next phase
+    fn end_voting(&mut self) {
         if let GamePhase::Voting { votes, .. } = &self.phase {
-            let voters = self.players.iter().filter(|p| p.is_alive);
-
-            let idle_voters = voters.filter(|p| !votes.contains_key(&p.chat_id));
-
-            idle_voters.count() == 0
-        } else {
-            panic!("should_end_voting called when not in GamePhase::Voting")
-        }
-    }
-
-    fn end_voting(&mut self) -> Option<&GamePhase> {
-        self.previous = Some(Box::new(self.clone()));
-
-        if let GamePhase::Voting { votes, .. } = &self.phase {
-            if !self.should_end_voting() {
-                return None;
-            }
-
             let mut tally = HashMap::new();
-            for v in self.get_vote_options() {
+            for v in self.vote_options() {
                 tally.insert(v.0, 0);
             }
             for v in votes.values() {
@@ -123,26 +324,37 @@ impl GameV1 {
             let tied_count = tied_targets.count();
             let is_voting_stalemate = self.is_voting_stalemate();
 
+            self.private_messages.clear();
             (self.phase, self.transition_message) = if is_voting_stalemate {
                 (
                     GamePhase::Night {
                         actions: Vec::new(),
                     },
-                    format!("No change in votes 2 rounds in a row. Moving to night time..."),
+                    String::from("No change in votes 2 rounds in a row. Moving to night time..."),
                 )
             } else if tied_count == 1 && top_target == &VOTE_OPTION_NOBODY {
                 (
                     GamePhase::Night {
                         actions: Vec::new(),
                     },
-                    format!("Most popular vote was not to lynch. Moving to night time..."),
+                    String::from("Most popular vote was not to lynch. Moving to night time..."),
+                )
+            } else if tied_count == 1 && self.setup.allow_defense {
+                let defendant_username = &self.get_player(*top_target).unwrap().username;
+                (
+                    GamePhase::Defense {
+                        defendant_id: *top_target,
+                    },
+                    format!(
+                        "{defendant_username} has been put on trial. Only they may speak while \
+                        they make their defense"
+                    ),
                 )
             } else if tied_count == 1 {
                 let defendant_username = &self.get_player(*top_target).unwrap().username;
                 (
                     GamePhase::Trial {
                         defendant_id: *top_target,
-                        poll_id_map: HashMap::new(),
                         verdicts: HashMap::new(),
                     },
                     format!("Now begins the trial for {defendant_username}:",),
@@ -150,77 +362,78 @@ impl GameV1 {
             } else {
                 (
                     GamePhase::Voting {
-                        poll_id_map: HashMap::new(),
                         votes: HashMap::new(),
                     },
-                    format!("Multiple options were tied for first place. Moving to re-vote"),
+                    String::from("Multiple options were tied for first place. Moving to re-vote"),
                 )
             };
-
-            return Some(&self.phase);
         } else {
             panic!("end_voting called when not in GamePhase::Voting")
         }
     }
 
-    fn should_end_trial(&self) -> bool {
-        if let GamePhase::Trial {
-            verdicts,
-            defendant_id: defendant,
-            ..
-        } = &self.phase
-        {
-            let jurors = self
-                .players
-                .iter()
-                .filter(|p: &&Player| p.is_alive && p.chat_id != *defendant);
-
-            let idle_jurors = jurors.filter(|p| !verdicts.contains_key(&p.chat_id));
+    /// Ends the defendant's defense and moves to their trial
+    fn end_defense(&mut self) {
+        let GamePhase::Defense { defendant_id } = self.phase else {
+            panic!("end_defense called when not in GamePhase::Defense")
+        };
 
-            idle_jurors.count() == 0
-        } else {
-            panic!("should_end_trial called when not in GamePhase::Trial")
-        }
+        self.private_messages.clear();
+        self.transition_message = format!(
+            "Now begins the trial for {}:",
+            self.get_username(defendant_id)
+        );
+        self.phase = GamePhase::Trial {
+            defendant_id,
+            verdicts: HashMap::new(),
+        };
     }
 
-    fn end_trial(&mut self) -> Option<&GamePhase> {
-        if !self.should_end_trial() {
-            return None;
-        }
+    /// Counts the verdicts and moves to the next phase. Missing verdicts are treated as abstentions,
+    /// and a defendant is only lynched with at least one guilty verdict
+    fn end_trial(&mut self) {
+        let (defendant_id, (defendant_name, death_name), guilties, innocents) =
+            if let GamePhase::Trial {
+                defendant_id,
+                verdicts,
+                ..
+            } = &self.phase
+            {
+                let guilties = verdicts
+                    .values()
+                    .filter(|v| matches!(v, Verdict::Guilty))
+                    .count();
+                let innocents = verdicts
+                    .values()
+                    .filter(|v| matches!(v, Verdict::Innocent))
+                    .count();
 
-        let (defendant_id, defendant_name, guilties, innocents) = if let GamePhase::Trial {
-            defendant_id,
-            verdicts,
-            ..
-        } = &self.phase
-        {
-            let guilties = verdicts
-                .values()
-                .filter(|v| matches!(v, Verdict::Guilty))
-                .count();
-            let innocents = verdicts
-                .values()
-                .filter(|v| matches!(v, Verdict::Innocent))
-                .count();
-
-            let username = self.get_player(*defendant_id).unwrap().username.clone();
-
-            (defendant_id, username, guilties, innocents)
-        } else {
-            panic!("end_trial called when not in GamePhase::Trial")
-        };
+                let defendant = self.get_player(*defendant_id).unwrap();
+                let names = (
+                    defendant.username.clone(),
+                    self.setup.get_death_name(defendant),
+                );
+
+                (defendant_id, names, guilties, innocents)
+            } else {
+                panic!("end_trial called when not in GamePhase::Trial")
+            };
 
-        self.transition_message = if guilties >= innocents {
+        let defendant_id = *defendant_id;
+        // Jurors who never answered cannot convict the defendant by default
+        let is_lynched = guilties > 0 && guilties >= innocents;
+        self.private_messages.clear();
+        self.transition_message = if is_lynched {
             let victim = self
                 .players
                 .iter_mut()
-                .find(|p| p.chat_id == *defendant_id)
+                .find(|p| p.player_id == defendant_id)
                 .unwrap();
             victim.is_alive = false;
 
             format!(
                 "By a vote of {guilties} guilty to {innocents} innocent, {} was lynched",
-                defendant_name
+                death_name
             )
         } else {
             format!(
@@ -229,135 +442,709 @@ impl GameV1 {
             )
         };
 
+        self.phase = if let Some(winning_faction) = self.get_winner() {
+            self.transition_message = format!(
+                "{}\n{} won the game!",
+                self.transition_message, winning_faction
+            );
+            GamePhase::Ending
+        } else if is_lynched && self.setup.allow_last_words {
+            self.transition_message = format!(
+                "{}\n{defendant_name} may now say their last words",
+                self.transition_message
+            );
+            GamePhase::LastWords {
+                player_id: defendant_id,
+            }
+        } else {
+            GamePhase::Night {
+                actions: Vec::new(),
+            }
+        };
+
+        // A player saying their last words joins the graveyard once they are over
+        if let GamePhase::LastWords { .. } = self.phase {
+            self.private_messages
+                .entry(defendant_id)
+                .or_default()
+                .push(String::from(
+                    "You have been lynched. Every player will see the messages you send until \
+                    your last words are over",
+                ));
+        } else if is_lynched {
+            self.welcome_to_graveyard(defendant_id);
+        }
+    }
+
+    /// Ends the lynched player's last words and moves to the night
+    fn end_last_words(&mut self) {
+        let GamePhase::LastWords { player_id } = self.phase else {
+            panic!("end_last_words called when not in GamePhase::LastWords")
+        };
+
+        self.private_messages.clear();
+        self.welcome_to_graveyard(player_id);
+        self.transition_message = format!(
+            "{}'s last words are over. Moving to night time...",
+            self.get_username(player_id)
+        );
         self.phase = GamePhase::Night {
             actions: Vec::new(),
         };
-
-        Some(&self.phase)
     }
 
     fn is_voting_stalemate(&self) -> bool {
         if let GamePhase::Voting { votes, .. } = &self.phase {
-            if let Some(inner) = &self.previous {
-                if let GameV1 {
-                    phase: GamePhase::Voting { votes: prev, .. },
-                    ..
-                } = &**inner
-                {
-                    if votes.len() != prev.len() {
-                        return false;
-                    }
-                    for (chat_id, targets) in votes.iter() {
-                        if let Some(reference) = prev.get(chat_id) {
-                            let mut old_set = HashSet::new();
-                            for r in reference {
-                                old_set.insert(r);
-                            }
+            // Compare against the previous phase, if it was a vote too
+            if let Some(PhaseRecord {
+                phase: GamePhase::Voting { votes: prev, .. },
+                ..
+            }) = self.history.back()
+            {
+                if votes.len() != prev.len() {
+                    return false;
+                }
+                for (player_id, targets) in votes.iter() {
+                    if let Some(reference) = prev.get(player_id) {
+                        let mut old_set = HashSet::new();
+                        for r in reference {
+                            old_set.insert(r);
+                        }
 
-                            let mut curr_set = HashSet::new();
-                            for t in targets {
-                                curr_set.insert(t);
-                            }
+                        let mut curr_set = HashSet::new();
+                        for t in targets {
+                            curr_set.insert(t);
+                        }
 
-                            if !curr_set.is_subset(&old_set) || !old_set.is_subset(&curr_set) {
-                                return false;
-                            }
-                        } else {
+                        if !curr_set.is_subset(&old_set) || !old_set.is_subset(&curr_set) {
                             return false;
                         }
+                    } else {
+                        return false;
                     }
-                    return true;
                 }
+                return true;
             }
         }
         false
     }
 
-    /// Returns None if there are no winners, and Some(String) if there is a winner,
-    /// where String is the faction of the winner
-    fn get_winner(&self) -> Option<String> {
-        let mafia_count = self
+    /// Tells a newly dead player every player's role, now that they are in the graveyard
+    fn welcome_to_graveyard(&mut self, player_id: PlayerId) {
+        let roles = self
             .players
             .iter()
-            .filter(|p| matches!(p.role, Role::Mafia))
-            .count();
-        let civilian_count = self
+            .map(|p| format!("{}: {}", p.username, p.role))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.private_messages
+            .entry(player_id)
+            .or_default()
+            .push(format!(
+                "You are dead. Messages you send now only reach the other dead players, \
+            and you will see the living players' day chat.\n\nRoles:\n{roles}"
+            ));
+    }
+
+    /// Returns the players that can be voted for, followed by Nobody if the setup allows it
+    fn vote_options(&self) -> Vec<(PlayerId, String)> {
+        let mut options = self
             .players
             .iter()
-            .filter(|p| !matches!(p.role, Role::Civilian))
-            .count();
-        if mafia_count == 0 {
-            Some(String::from("Civilians"))
-        } else if mafia_count >= civilian_count {
-            Some(String::from("Mafia"))
-        } else {
-            None
+            .filter(|p| p.is_alive)
+            .map(|p| (p.player_id, p.username.clone()))
+            .collect::<Vec<_>>();
+
+        if self.setup.allow_no_lynch {
+            options.push((VOTE_OPTION_NOBODY, String::from(NOBODY_USERNAME)));
         }
+        options
     }
 
-    fn get_player(&self, chat_id: ChatId) -> Option<&Player> {
-        self.players.iter().find(|p| p.chat_id == chat_id)
+    fn get_player(&self, player_id: PlayerId) -> Option<&Player> {
+        self.players.iter().find(|p| p.player_id == player_id)
+    }
+
+    /// Returns the number of the current or most recent day. Each night is followed by the day
+    /// with the same number
+    fn get_day(&self) -> usize {
+        match self.setup.start_phase {
+            StartPhase::Night => self.night - 1,
+            StartPhase::Day => self.night,
+        }
+    }
+
+    fn get_username(&self, player_id: PlayerId) -> &str {
+        self.get_player(player_id)
+            .map_or(NOBODY_USERNAME, |p| p.username.as_str())
+    }
+
+    fn join_usernames(&self, player_ids: impl Iterator<Item = PlayerId>) -> String {
+        player_ids
+            .map(|id| self.get_username(id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Describes how the player's game went, e.g. "killed on night 2"
+    fn get_fate(&self, player_id: PlayerId) -> String {
+        for entry in self.timeline.iter() {
+            match entry {
+                TimelineEntry::Night { night, killed } if killed.contains(&player_id) => {
+                    return format!("killed on night {night}")
+                }
+                TimelineEntry::Trial {
+                    day,
+                    defendant_id,
+                    lynched: true,
+                    ..
+                } if *defendant_id == player_id => return format!("lynched on day {day}"),
+                TimelineEntry::Quit {
+                    player_id: id,
+                    time,
+                } if *id == player_id => return format!("quit on {time}"),
+                TimelineEntry::Modkilled {
+                    player_id: id,
+                    time,
+                } if *id == player_id => return format!("removed by the host on {time}"),
+                TimelineEntry::Substituted {
+                    player_id: id,
+                    substitute_id,
+                    ..
+                } if *id == player_id => {
+                    return format!("replaced by {}", self.get_username(*substitute_id))
+                }
+                _ => {}
+            }
+        }
+        String::from("survived")
     }
 }
 
 impl Game for GameV1 {
-    fn from_lobby(lobby: &crate::lobby_manager::Lobby) -> Self
+    fn from_lobby(lobby: &crate::lobby_manager::Lobby, seed: u64) -> Self
     where
         Self: Sized,
     {
-        let player_count = lobby.users.len();
-        let mut roles = vec![Role::Civilian; player_count];
-        roles[0] = Role::Mafia;
-        roles.shuffle(&mut thread_rng());
+        let setup = lobby.setup.clone();
+        let mut roles = setup.get_roles(lobby.users.len());
+        roles.shuffle(&mut StdRng::seed_from_u64(seed));
 
         let players = lobby
             .users
             .iter()
             .zip(roles)
             .map(|(p, r)| Player {
-                chat_id: p.chat_id,
+                player_id: p.player_id,
                 username: p.username.clone(),
                 is_alive: true,
+                has_left: false,
                 role: r,
             })
             .collect::<Vec<_>>();
 
-        GameV1 {
+        GameV1::start(players, setup, seed, Some(lobby.host_id))
+    }
+
+    fn from_json(json: &str) -> serde_json::Result<Self>
+    where
+        Self: Sized,
+    {
+        from_versioned_json(json)
+    }
+
+    fn replay(events: &[GameEvent]) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        let Some(GameEvent::Started {
             players,
-            phase: GamePhase::Night {
-                actions: Vec::new(),
-            },
-            previous: None,
-            transition_message: String::from("Welcome to the Mafia Game"),
+            setup,
+            seed,
+            host_id,
+        }) = events.first()
+        else {
+            return Err(String::from("The first event must be GameEvent::Started"));
+        };
+
+        let mut game = GameV1::start(players.clone(), setup.clone(), *seed, *host_id);
+        for (i, event) in events.iter().enumerate().skip(1) {
+            game.apply_event(event)
+                .map_err(|err| format!("Failed to apply event {i}: {err}"))?;
         }
+        game.unsaved_events.clear();
+
+        Ok(game)
     }
 
     fn snapshot(&self) -> Box<dyn Game> {
         Box::new(self.clone())
     }
 
+    fn to_json(&self) -> serde_json::Result<String> {
+        to_versioned_json(self)
+    }
+
+    fn get_setup(&self) -> &GameSetup {
+        &self.setup
+    }
+
+    fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn get_host_id(&self) -> Option<PlayerId> {
+        self.host_id
+            .or_else(|| self.get_players().first().map(|p| p.player_id))
+    }
+
+    fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    fn set_paused(&mut self, paused: bool) -> Result<(), GameError> {
+        if matches!(self.phase, GamePhase::Ending) {
+            return Err(GameError::WrongPhase);
+        }
+        if self.is_paused != paused {
+            self.is_paused = paused;
+            self.unsaved_events.push(GameEvent::Paused { paused });
+        }
+        Ok(())
+    }
+
     fn get_players(&self) -> Vec<&Player> {
-        self.players.iter().collect::<Vec<_>>()
+        self.players
+            .iter()
+            .filter(|p| !p.has_left)
+            .collect::<Vec<_>>()
     }
 
     fn get_phase(&self) -> &GamePhase {
         &self.phase
     }
 
+    fn get_phase_number(&self) -> usize {
+        self.phase_number
+    }
+
+    fn get_history(&self) -> Vec<&PhaseRecord> {
+        self.history.iter().collect()
+    }
+
+    fn quit(&mut self, player_id: PlayerId) -> Result<Option<&GamePhase>, GameError> {
+        if matches!(self.phase, GamePhase::Ending) {
+            return Err(GameError::WrongPhase);
+        }
+        let time = self.get_time();
+        let was_host = self.get_host_id() == Some(player_id);
+        let player = self
+            .players
+            .iter_mut()
+            .find(|p| p.player_id == player_id && !p.has_left)
+            .ok_or(GameError::NotAlive)?;
+        let was_alive = player.is_alive;
+        player.is_alive = false;
+        player.has_left = true;
+
+        // Hosting passes to the first living player, or to a dead one if nobody else is alive
+        if was_host {
+            self.host_id = self
+                .players
+                .iter()
+                .find(|p| p.is_alive)
+                .or_else(|| self.players.iter().find(|p| !p.has_left))
+                .or_else(|| self.players.first())
+                .map(|p| p.player_id);
+        }
+        self.unsaved_events.push(GameEvent::Quit { player_id });
+        if !was_alive {
+            return Ok(None);
+        }
+        self.timeline.push(TimelineEntry::Quit { player_id, time });
+
+        Ok(self.drop_inputs_of(player_id).then_some(&self.phase))
+    }
+
+    fn modkill(&mut self, player_id: PlayerId) -> Result<Option<&GamePhase>, GameError> {
+        if matches!(self.phase, GamePhase::Ending) {
+            return Err(GameError::WrongPhase);
+        }
+        let time = self.get_time();
+        let player = self
+            .players
+            .iter_mut()
+            .find(|p| p.player_id == player_id && p.is_alive)
+            .ok_or(GameError::NotAlive)?;
+        player.is_alive = false;
+
+        self.unsaved_events.push(GameEvent::Modkilled { player_id });
+        self.timeline
+            .push(TimelineEntry::Modkilled { player_id, time });
+
+        let ended_phase = self.drop_inputs_of(player_id);
+        self.welcome_to_graveyard(player_id);
+        Ok(ended_phase.then_some(&self.phase))
+    }
+
+    fn substitute_player(
+        &mut self,
+        player_id: PlayerId,
+        substitute_id: PlayerId,
+        username: String,
+    ) -> Result<(), GameError> {
+        if matches!(self.phase, GamePhase::Ending) {
+            return Err(GameError::WrongPhase);
+        }
+        if self.players.iter().any(|p| p.player_id == substitute_id) {
+            return Err(GameError::NotAllowed);
+        }
+        let index = self
+            .players
+            .iter()
+            .position(|p| p.player_id == player_id && p.is_alive)
+            .ok_or(GameError::NotAlive)?;
+
+        // The player who left stays in the game's records under their old id, so that the
+        // summary can still name them. The substitute takes their place in the player order
+        let player = &mut self.players[index];
+        let mut substitute = player.clone();
+        player.is_alive = false;
+        player.has_left = true;
+        substitute.player_id = substitute_id;
+        substitute.username = username.clone();
+        let former_username = player.username.clone();
+        if self.get_host_id() == Some(player_id) {
+            self.host_id = Some(substitute_id);
+        }
+        self.players.insert(index + 1, substitute);
+
+        GameV1::replace_in_phase(&mut self.phase, player_id, substitute_id);
+        for record in self.history.iter_mut() {
+            GameV1::replace_in_phase(&mut record.phase, player_id, substitute_id);
+        }
+        for investigation in self.investigations.iter_mut() {
+            if investigation.investigator_id == player_id {
+                investigation.investigator_id = substitute_id;
+            }
+        }
+        if let Some(messages) = self.private_messages.remove(&player_id) {
+            self.private_messages.insert(substitute_id, messages);
+        }
+
+        self.timeline.push(TimelineEntry::Substituted {
+            player_id,
+            username: former_username,
+            substitute_id,
+        });
+        self.unsaved_events.push(GameEvent::Substituted {
+            player_id,
+            substitute_id,
+            username,
+        });
+        Ok(())
+    }
+
+    fn get_summary(&self) -> String {
+        let mut summary = String::from("Roles:");
+        for p in self.players.iter() {
+            summary.push_str(&format!(
+                "\n{}: {}, {}",
+                p.username,
+                p.role,
+                self.get_fate(p.player_id)
+            ));
+        }
+
+        for entry in self.timeline.iter() {
+            summary.push_str("\n\n");
+            match entry {
+                TimelineEntry::Night { night, killed } if killed.is_empty() => {
+                    summary.push_str(&format!("Night {night}: Nobody died"))
+                }
+                TimelineEntry::Night { night, killed } => summary.push_str(&format!(
+                    "Night {night}: {} died",
+                    self.join_usernames(killed.iter().copied())
+                )),
+                TimelineEntry::Voting { day, votes } => {
+                    summary.push_str(&format!("Day {day} votes:"));
+                    for (voter_id, target_ids) in votes.iter() {
+                        let targets = if target_ids.is_empty() {
+                            String::from("did not vote")
+                        } else {
+                            self.join_usernames(target_ids.iter().copied())
+                        };
+                        summary
+                            .push_str(&format!("\n{} → {targets}", self.get_username(*voter_id)));
+                    }
+                }
+                TimelineEntry::Quit { player_id, time } => {
+                    summary.push_str(&format!("{} quit on {time}", self.get_username(*player_id)))
+                }
+                TimelineEntry::Modkilled { player_id, time } => summary.push_str(&format!(
+                    "{} was removed by the host on {time}",
+                    self.get_username(*player_id)
+                )),
+                TimelineEntry::Substituted {
+                    username,
+                    substitute_id,
+                    ..
+                } => summary.push_str(&format!(
+                    "{} took over from {username}",
+                    self.get_username(*substitute_id)
+                )),
+                TimelineEntry::Trial {
+                    day,
+                    defendant_id,
+                    verdicts,
+                    lynched,
+                } => {
+                    summary.push_str(&format!(
+                        "Day {day} trial of {}: {}",
+                        self.get_username(*defendant_id),
+                        if *lynched { "lynched" } else { "released" }
+                    ));
+                    // The trial poll is anonymous, so only the verdict counts are shown
+                    for verdict in [Verdict::Guilty, Verdict::Innocent, Verdict::Abstain] {
+                        let count = verdicts.iter().filter(|(_, v)| *v == verdict).count();
+                        if count > 0 {
+                            summary.push_str(&format!("\n{verdict}: {count}"));
+                        }
+                    }
+                }
+            }
+        }
+        summary
+    }
+
+    fn take_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.unsaved_events)
+    }
+
+    fn apply_event(&mut self, event: &GameEvent) -> Result<(), String> {
+        match event {
+            GameEvent::Started { .. } => Err(String::from("The game has already started")),
+            GameEvent::NightAction {
+                player_id,
+                target_id,
+            } => self
+                .add_night_action(*player_id, *target_id)
+                .map_err(|err| err.to_string()),
+            GameEvent::Vote {
+                player_id,
+                option_ids,
+            } => self
+                .add_vote(*player_id, option_ids.clone())
+                .map_err(|err| err.to_string()),
+            GameEvent::Verdict {
+                player_id,
+                option_id,
+            } => self
+                .add_verdict(*player_id, *option_id)
+                .map_err(|err| err.to_string()),
+            GameEvent::Quit { player_id } => self
+                .quit(*player_id)
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            GameEvent::Modkilled { player_id } => self
+                .modkill(*player_id)
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            GameEvent::Paused { paused } => self.set_paused(*paused).map_err(|err| err.to_string()),
+            GameEvent::Substituted {
+                player_id,
+                substitute_id,
+                username,
+            } => self
+                .substitute_player(*player_id, *substitute_id, username.clone())
+                .map_err(|err| err.to_string()),
+            GameEvent::PhaseEnded => match self.end_phase() {
+                Some(_) => Ok(()),
+                None => Err(String::from("The phase could not end yet")),
+            },
+            GameEvent::PhaseForced => match self.force_end_phase() {
+                Some(_) => Ok(()),
+                None => Err(String::from("The game has already ended")),
+            },
+        }
+    }
+
+    fn get_winner(&self) -> Option<String> {
+        let mafia_count = self
+            .players
+            .iter()
+            .filter(|p| p.is_alive && matches!(p.role, Role::Mafia))
+            .count();
+        let civilian_count = self
+            .players
+            .iter()
+            .filter(|p| p.is_alive && !matches!(p.role, Role::Mafia))
+            .count();
+        if mafia_count == 0 {
+            Some(String::from("Civilians"))
+        } else if mafia_count >= civilian_count {
+            Some(String::from("Mafia"))
+        } else {
+            None
+        }
+    }
+
+    fn get_idle_players(&self) -> Vec<&Player> {
+        let is_idle = |p: &&Player| -> bool {
+            if !p.is_alive {
+                return false;
+            }
+            match &self.phase {
+                GamePhase::Night { actions } => {
+                    let has_acted = actions.iter().any(|a| a.source() == p.player_id);
+                    match (p.role, self.setup.team_kill_mode) {
+                        // Every Mafia stays idle until they have agreed on a victim
+                        (Role::Mafia, TeamKillMode::Majority) => {
+                            !has_acted
+                                || get_team_kill(&self.players, actions, self.setup.team_kill_mode)
+                                    .is_none()
+                        }
+                        (Role::Mafia, TeamKillMode::DesignatedKiller) => {
+                            let is_killer = get_designated_killer(&self.players)
+                                .is_some_and(|killer| killer.player_id == p.player_id);
+                            is_killer && !has_acted
+                        }
+                        (Role::Doctor | Role::Detective, _) => !has_acted,
+                        (Role::Civilian, _) => false,
+                    }
+                }
+                GamePhase::Voting { votes, .. } => !votes.contains_key(&p.player_id),
+                GamePhase::Trial {
+                    defendant_id,
+                    verdicts,
+                    ..
+                } => p.player_id != *defendant_id && !verdicts.contains_key(&p.player_id),
+                GamePhase::Defense { .. } | GamePhase::LastWords { .. } | GamePhase::Ending => {
+                    false
+                }
+            }
+        };
+
+        self.players.iter().filter(is_idle).collect::<Vec<_>>()
+    }
+
     fn end_phase(&mut self) -> Option<&GamePhase> {
-        match &mut self.phase {
-            GamePhase::Night { .. } => self.end_night(),
-            GamePhase::Voting { .. } => self.end_voting(),
-            GamePhase::Trial { .. } => self.end_trial(),
-            _ => None,
+        // Players are given the whole of a phase in which they speak
+        let is_timed = matches!(
+            self.phase,
+            GamePhase::Defense { .. } | GamePhase::LastWords { .. }
+        );
+        if self.is_paused
+            || is_timed
+            || !self.get_idle_players().is_empty()
+            || !self.end_current_phase()
+        {
+            return None;
+        }
+
+        self.unsaved_events.push(GameEvent::PhaseEnded);
+        Some(&self.phase)
+    }
+
+    fn force_end_phase(&mut self) -> Option<&GamePhase> {
+        if !self.end_current_phase() {
+            return None;
         }
+
+        self.unsaved_events.push(GameEvent::PhaseForced);
+        Some(&self.phase)
     }
 
     fn get_transition_message(&self) -> String {
         self.transition_message.clone()
     }
 
-    fn get_night_actions(&self) -> HashMap<ChatId, (String, Vec<(ChatId, String)>)> {
+    fn get_private_messages(&self) -> HashMap<PlayerId, Vec<String>> {
+        self.private_messages.clone()
+    }
+
+    fn get_investigations(&self, investigator_id: PlayerId) -> Vec<&Investigation> {
+        self.investigations
+            .iter()
+            .filter(|i| i.investigator_id == investigator_id)
+            .collect::<Vec<_>>()
+    }
+
+    fn route_chat_message(&self, sender_id: PlayerId) -> ChatRoute {
+        let reject = |reason: &str| ChatRoute::Reject {
+            reason: String::from(reason),
+        };
+
+        let recipients_where = |filter: &dyn Fn(&Player) -> bool| -> Vec<PlayerId> {
+            self.players
+                .iter()
+                .filter(|p| p.player_id != sender_id && !p.has_left && filter(p))
+                .map(|p| p.player_id)
+                .collect()
+        };
+
+        let is_saying_last_words = matches!(
+            self.phase,
+            GamePhase::LastWords { player_id } if player_id == sender_id
+        );
+
+        match self.get_player(sender_id) {
+            None => reject("You are not in this game"),
+            // The lynched player's last words reach every player, like the day chat
+            Some(_) if is_saying_last_words => ChatRoute::Relay {
+                channel: ChatChannel::Day,
+                recipients: recipients_where(&|_| true),
+            },
+            Some(sender) if !sender.is_alive => {
+                let recipients = recipients_where(&|p| !p.is_alive);
+                if recipients.is_empty() {
+                    reject("Nobody else is in the graveyard yet")
+                } else {
+                    ChatRoute::Relay {
+                        channel: ChatChannel::Graveyard,
+                        recipients,
+                    }
+                }
+            }
+            Some(sender) => match &self.phase {
+                // Dead players also receive the day chat, but cannot reply to it
+                GamePhase::Voting { .. } | GamePhase::Trial { .. } => ChatRoute::Relay {
+                    channel: ChatChannel::Day,
+                    recipients: recipients_where(&|_| true),
+                },
+                GamePhase::Night { .. } if sender.role == Role::Mafia => {
+                    let recipients = recipients_where(&|p| p.is_alive && p.role == Role::Mafia);
+                    if recipients.is_empty() {
+                        reject("There are no other Mafia left to talk to")
+                    } else {
+                        ChatRoute::Relay {
+                            channel: ChatChannel::Mafia,
+                            recipients,
+                        }
+                    }
+                }
+                GamePhase::Defense { defendant_id } if *defendant_id == sender_id => {
+                    ChatRoute::Relay {
+                        channel: ChatChannel::Day,
+                        recipients: recipients_where(&|_| true),
+                    }
+                }
+                GamePhase::Defense { defendant_id } => reject(&format!(
+                    "Only {} may speak during their defense",
+                    self.get_username(*defendant_id)
+                )),
+                GamePhase::LastWords { player_id } => reject(&format!(
+                    "Only {} may speak during their last words",
+                    self.get_username(*player_id)
+                )),
+                GamePhase::Night { .. } => reject("You are asleep. Wait for the day to talk"),
+                GamePhase::Ending => reject("The game is over"),
+            },
+        }
+    }
+
+    fn get_night_actions(&self) -> HashMap<PlayerId, (String, Vec<(PlayerId, String)>)> {
         let mut result = HashMap::new();
         for p in self.players.iter() {
             if !p.is_alive {
@@ -365,153 +1152,574 @@ impl Game for GameV1 {
             }
             let (text, targets) = match p.role {
                 Role::Mafia => {
-                    let mut options: Vec<(ChatId, String)> = self
+                    let mut options: Vec<(PlayerId, String)> = self
                         .players
                         .iter()
                         .filter(|p| p.is_alive && p.role != Role::Mafia)
-                        .map(|p| (p.chat_id, p.username.clone()))
+                        .map(|p| (p.player_id, p.username.clone()))
+                        .collect();
+
+                    options.push((NOBODY_PLAYER_ID, NOBODY_USERNAME.to_string()));
+                    let text = match self.setup.team_kill_mode {
+                        TeamKillMode::Majority => String::from(
+                            "You are a Mafia. Pick a victim to kill, the Mafia's most popular pick \
+                            will be killed. You can change your pick until the night ends:",
+                        ),
+                        TeamKillMode::DesignatedKiller => {
+                            match get_designated_killer(&self.players) {
+                                Some(killer) if killer.player_id == p.player_id => String::from(
+                                    "You are the Mafia's killer. Pick a victim to kill. \
+                                    You can change your pick until the night ends:",
+                                ),
+                                killer => format!(
+                                    "You are a Mafia. Suggest a victim for {} to kill:",
+                                    killer.map_or("the killer", |k| &k.username)
+                                ),
+                            }
+                        }
+                    };
+                    (text, options)
+                }
+                Role::Doctor => {
+                    let options: Vec<(PlayerId, String)> = self
+                        .players
+                        .iter()
+                        .filter(|p| p.is_alive)
+                        .map(|p| (p.player_id, p.username.clone()))
                         .collect();
 
-                    options.push((NOBODY_CHAT_ID, NOBODY_USERNAME.to_string()));
                     (
-                        String::from("You are a Mafia. Pick a victim to kil:"),
+                        String::from("You are a Doctor. Pick a player to protect:"),
                         options,
                     )
                 }
+                Role::Detective => {
+                    let options: Vec<(PlayerId, String)> = self
+                        .players
+                        .iter()
+                        .filter(|target| target.is_alive && target.player_id != p.player_id)
+                        .map(|target| (target.player_id, target.username.clone()))
+                        .collect();
+
+                    let mut text = String::from("You are a Detective.");
+                    for investigation in self.get_investigations(p.player_id) {
+                        text.push_str(&format!("\n{investigation}"));
+                    }
+                    text.push_str("\nPick a player to investigate:");
+
+                    (text, options)
+                }
                 Role::Civilian => (String::from("You are a Civilian"), Vec::new()),
             };
-            result.insert(p.chat_id, (text, targets));
+            result.insert(p.player_id, (text, targets));
         }
 
         result
     }
 
-    fn add_night_action(&mut self, actor_id: ChatId, target_id: ChatId) {
-        let options = self.get_night_actions().get(&actor_id).unwrap().1.clone();
-        let is_valid_target = options
+    fn get_team_picks(&self) -> Result<(Vec<PlayerId>, String), GameError> {
+        let GamePhase::Night { actions } = &self.phase else {
+            return Err(GameError::WrongPhase);
+        };
+
+        let mafia = self
+            .players
             .iter()
-            .find(|(chat_id, _)| *chat_id == target_id)
-            .is_some();
-        assert!(
-            is_valid_target,
-            "add_night_action called with illegal target_id"
-        );
+            .filter(|p| p.is_alive && p.role == Role::Mafia)
+            .collect::<Vec<_>>();
 
-        let actor_role = self.get_player(actor_id).unwrap().role;
-        let action_opt = match actor_role {
-            Role::Mafia => Some(Action::Kill {
+        let mut lines = vec![String::from("The Mafia's current picks:")];
+        for member in mafia.iter() {
+            let pick = actions.iter().find_map(|a| match a {
+                Action::Kill { source, target } if *source == member.player_id => Some(*target),
+                _ => None,
+            });
+            let pick_username = match pick {
+                Some(NOBODY_PLAYER_ID) => NOBODY_USERNAME,
+                Some(target_id) => self
+                    .get_player(target_id)
+                    .map_or(NOBODY_USERNAME, |t| &t.username),
+                None => "(no pick yet)",
+            };
+            lines.push(format!("{}: {}", member.username, pick_username));
+        }
+
+        Ok((
+            mafia.iter().map(|p| p.player_id).collect(),
+            lines.join("\n"),
+        ))
+    }
+
+    fn add_night_action(
+        &mut self,
+        actor_id: PlayerId,
+        target_id: PlayerId,
+    ) -> Result<(), GameError> {
+        if !matches!(self.phase, GamePhase::Night { .. }) {
+            return Err(GameError::WrongPhase);
+        }
+        if self.is_paused {
+            return Err(GameError::Paused);
+        }
+        let actor = self
+            .get_player(actor_id)
+            .filter(|p| p.is_alive)
+            .ok_or(GameError::NotAlive)?;
+        let actor_role = actor.role;
+
+        let night_actions = self.get_night_actions();
+        let (_, options) = night_actions.get(&actor_id).ok_or(GameError::NotAllowed)?;
+        if !options.iter().any(|(player_id, _)| *player_id == target_id) {
+            return Err(GameError::InvalidTarget);
+        }
+
+        let action = match actor_role {
+            Role::Mafia => Action::Kill {
                 source: actor_id,
                 target: target_id,
-            }),
-            _ => None,
+            },
+            Role::Doctor => Action::Protect {
+                source: actor_id,
+                target: target_id,
+            },
+            Role::Detective => Action::Investigate {
+                source: actor_id,
+                target: target_id,
+            },
+            Role::Civilian => return Err(GameError::NotAllowed),
         };
 
         if let GamePhase::Night { actions, .. } = &mut self.phase {
-            if let Some(action) = action_opt {
-                actions.push(action);
-            }
-        } else {
-            panic!("add_night_action called when not GamePhase::Night")
-        };
+            // A player's latest choice replaces their earlier one, e.g. when prompts are re-sent
+            actions.retain(|a| a.source() != actor_id);
+            actions.push(action);
+        }
+        self.unsaved_events.push(GameEvent::NightAction {
+            player_id: actor_id,
+            target_id,
+        });
+        Ok(())
     }
 
-    fn get_vote_options(&self) -> Vec<(ChatId, String)> {
+    fn get_vote_options(&self) -> Result<Vec<(PlayerId, String)>, GameError> {
         if let GamePhase::Voting { .. } = self.phase {
-            let base_options = vec![(VOTE_OPTION_NOBODY, String::from("Nobody"))];
-
-            let mut options = self
-                .players
-                .iter()
-                .filter(|p| p.is_alive)
-                .map(|p| (p.chat_id, p.username.clone()))
-                .chain(base_options)
-                .collect::<Vec<_>>();
-
-            options.push((VOTE_OPTION_NOBODY, String::from("Nobody")));
-            options
+            Ok(self.vote_options())
         } else {
-            panic!("get_vote_options called when not in GamePhase::Voting")
+            Err(GameError::WrongPhase)
         }
     }
 
-    fn get_voters(&self) -> Vec<&Player> {
+    fn get_voters(&self) -> Result<Vec<&Player>, GameError> {
         if let GamePhase::Voting { .. } = self.phase {
-            self.players
+            Ok(self
+                .players
                 .iter()
                 .filter(|p| p.is_alive)
-                .collect::<Vec<_>>()
+                .collect::<Vec<_>>())
         } else {
-            panic!("get_voters called when not in GamePhase::Voting")
+            Err(GameError::WrongPhase)
         }
     }
 
-    fn add_vote(&mut self, voter_id: teloxide::types::ChatId, choices: Vec<i32>) {
-        let vote_options = self.get_vote_options();
-        let chosen_ids = choices
+    fn add_vote(&mut self, voter_id: PlayerId, choices: Vec<i32>) -> Result<(), GameError> {
+        let vote_options = self.get_vote_options()?;
+        if self.is_paused {
+            return Err(GameError::Paused);
+        }
+        if !self.get_player(voter_id).is_some_and(|p| p.is_alive) {
+            return Err(GameError::NotAlive);
+        }
+        let mut chosen_ids = choices
             .iter()
-            .map(|i| vote_options[*i as usize].0)
-            .collect::<Vec<_>>();
+            .map(|i| {
+                usize::try_from(*i)
+                    .ok()
+                    .and_then(|i| vote_options.get(i))
+                    .map(|(player_id, _)| *player_id)
+                    .ok_or(GameError::InvalidTarget)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // An option chosen more than once only counts once
+        let mut seen_ids = HashSet::new();
+        chosen_ids.retain(|player_id| seen_ids.insert(*player_id));
+        if chosen_ids.len() > 1 && !self.setup.allow_multiple_votes {
+            return Err(GameError::TooManyChoices);
+        }
 
         if let GamePhase::Voting { votes, .. } = &mut self.phase {
+            if votes.contains_key(&voter_id) {
+                return Err(GameError::AlreadyActed);
+            }
             votes.insert(voter_id, chosen_ids);
-        } else {
-            panic!("add_vote called when not in GamePhase::Voting")
         }
+        self.unsaved_events.push(GameEvent::Vote {
+            player_id: voter_id,
+            option_ids: choices,
+        });
+        Ok(())
     }
 
-    fn get_verdict_options(&self) -> Vec<Verdict> {
+    fn get_verdict_options(&self) -> Result<Vec<Verdict>, GameError> {
         if let GamePhase::Trial { .. } = self.phase {
-            vec![Verdict::Guilty, Verdict::Innocent, Verdict::Abstain]
+            Ok(vec![Verdict::Guilty, Verdict::Innocent, Verdict::Abstain])
         } else {
-            panic!("get_verdict_options called when not in GamePhase::Trial")
+            Err(GameError::WrongPhase)
         }
     }
 
-    fn get_jury(&self) -> Vec<&Player> {
+    fn get_jury(&self) -> Result<Vec<&Player>, GameError> {
         if let GamePhase::Trial {
             defendant_id: defendant,
             ..
         } = &self.phase
         {
-            self.players
+            Ok(self
+                .players
                 .iter()
-                .filter(|p| p.is_alive && p.chat_id != *defendant)
-                .collect::<Vec<_>>()
+                .filter(|p| p.is_alive && p.player_id != *defendant)
+                .collect::<Vec<_>>())
         } else {
-            panic!("get_jury called when not in GamePhase::Trial")
+            Err(GameError::WrongPhase)
         }
     }
 
-    fn add_verdict(&mut self, juror_id: ChatId, chosen: i32) {
-        let verdict = *self
-            .get_verdict_options()
-            .iter()
-            .nth(chosen as usize)
-            .unwrap();
-        if let GamePhase::Trial { verdicts, .. } = &mut self.phase {
-            verdicts.insert(juror_id, verdict);
-        } else {
-            panic!("add_verdict called when not in GamePhase::Trial")
+    fn add_verdict(&mut self, juror_id: PlayerId, chosen: i32) -> Result<(), GameError> {
+        let verdict = usize::try_from(chosen)
+            .ok()
+            .and_then(|i| self.get_verdict_options().ok()?.get(i).copied());
+        let is_alive = self.get_player(juror_id).is_some_and(|p| p.is_alive);
+
+        let GamePhase::Trial {
+            defendant_id,
+            verdicts,
+            ..
+        } = &mut self.phase
+        else {
+            return Err(GameError::WrongPhase);
+        };
+        if self.is_paused {
+            return Err(GameError::Paused);
+        }
+        if !is_alive {
+            return Err(GameError::NotAlive);
+        }
+        if *defendant_id == juror_id {
+            return Err(GameError::NotAllowed);
         }
+        if verdicts.contains_key(&juror_id) {
+            return Err(GameError::AlreadyActed);
+        }
+        verdicts.insert(juror_id, verdict.ok_or(GameError::InvalidTarget)?);
+        self.unsaved_events.push(GameEvent::Verdict {
+            player_id: juror_id,
+            option_id: chosen,
+        });
+        Ok(())
     }
+}
 
-    fn add_poll_msg_ids(&mut self, poll_msg_ids: HashMap<ChatId, MessageId>) {
-        match &mut self.phase {
-            GamePhase::Voting { poll_id_map, .. } => poll_msg_ids.clone_into(poll_id_map),
-            GamePhase::Trial { poll_id_map, .. } => poll_msg_ids.clone_into(poll_id_map),
-            _ => {
-                panic!("get_poll_msg_ids called when not in GamePhase::Voting or GamePhase::Trial")
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lobby_manager::Lobby;
+
+    fn get_roles(game: &GameV1) -> Vec<Role> {
+        game.players.iter().map(|p| p.role).collect()
+    }
+
+    /// Starts a game of `player_count` players with the given `/set` options
+    fn start_game(player_count: usize, options: &[(&str, &str)], seed: u64) -> GameV1 {
+        let mut lobby = Lobby::with_players(player_count, GameSetup::default());
+        for (option, value) in options {
+            lobby.setup.set_option(option, value, player_count).unwrap();
         }
+        GameV1::from_lobby(&lobby, seed)
     }
 
-    fn get_poll_msg_ids(&self) -> HashMap<ChatId, MessageId> {
-        match &self.phase {
-            GamePhase::Voting { poll_id_map, .. } => poll_id_map.clone(),
-            GamePhase::Trial { poll_id_map, .. } => poll_id_map.clone(),
-            _ => {
-                panic!("get_poll_msg_ids called when not in GamePhase::Voting or GamePhase::Trial")
+    fn kill(game: &mut GameV1, player_id: PlayerId) {
+        game.players
+            .iter_mut()
+            .find(|p| p.player_id == player_id)
+            .unwrap()
+            .is_alive = false;
+    }
+
+    fn get_ids_with_role(game: &GameV1, role: Role) -> Vec<PlayerId> {
+        game.players
+            .iter()
+            .filter(|p| p.role == role)
+            .map(|p| p.player_id)
+            .collect()
+    }
+
+    /// Returns the channel and the sorted recipients of a chat message sent by the player,
+    /// failing the test if the message is rejected
+    fn get_relay(game: &GameV1, sender_id: PlayerId) -> (ChatChannel, Vec<PlayerId>) {
+        match game.route_chat_message(sender_id) {
+            ChatRoute::Relay {
+                channel,
+                mut recipients,
+            } => {
+                recipients.sort_by_key(|id| id.0);
+                (channel, recipients)
             }
+            ChatRoute::Reject { reason } => panic!("Message from {sender_id} rejected: {reason}"),
         }
     }
+
+    fn get_votes(game: &GameV1, voter_id: PlayerId) -> Vec<PlayerId> {
+        match &game.phase {
+            GamePhase::Voting { votes } => votes.get(&voter_id).cloned().unwrap_or_default(),
+            _ => panic!("Not in GamePhase::Voting"),
+        }
+    }
+
+    #[test]
+    fn the_seed_decides_the_roles() {
+        let lobby = Lobby::with_players(8, GameSetup::default());
+        let deals = (0..20)
+            .map(|seed| get_roles(&GameV1::from_lobby(&lobby, seed)))
+            .collect::<Vec<_>>();
+        for (seed, roles) in (0..).zip(deals.iter()) {
+            assert_eq!(&get_roles(&GameV1::from_lobby(&lobby, seed)), roles);
+        }
+        assert!(
+            deals.iter().any(|roles| *roles != deals[0]),
+            "Different seeds deal different roles"
+        );
+    }
+
+    #[test]
+    fn games_saved_without_a_setup_use_the_default_setup() {
+        let game = GameV1::from_lobby(&Lobby::with_players(4, GameSetup::default()), 0);
+
+        let mut json: serde_json::Value = serde_json::from_str(&game.to_json().unwrap()).unwrap();
+        json["game"].as_object_mut().unwrap().remove("setup");
+        let restored = GameV1::from_json(&json.to_string()).unwrap();
+        assert_eq!(
+            restored.get_setup().describe(4),
+            GameSetup::default().describe(4)
+        );
+    }
+
+    #[test]
+    fn games_saved_in_the_first_format_version_can_be_read() {
+        let json = r#"{"version":1,"game":{
+            "players":[
+                {"chat_id":1,"username":"player1","role":"Mafia","is_alive":true},
+                {"chat_id":2,"username":"player2","role":"Civilian","is_alive":false},
+                {"chat_id":3,"username":"player3","role":"Civilian","is_alive":true}
+            ],
+            "phase":{"Night":{"actions":[{"Kill":{"source":1,"target":3}}]}},
+            "previous":null,
+            "transition_message":"player2 was lynched",
+            "private_messages":{},
+            "night":2,
+            "investigations":[],
+            "phase_number":3
+        }}"#;
+
+        let game = GameV1::from_json(json).unwrap();
+        let players = game.get_players();
+        let ids = players.iter().map(|p| p.player_id).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2, 3].map(PlayerId));
+        assert!(!players[1].is_alive());
+        assert_eq!(game.get_phase_number(), 3);
+    }
+
+    #[test]
+    fn only_one_option_can_be_voted_for_without_multiple_votes() {
+        let mut game = start_game(4, &[("start", "day"), ("multivote", "off")], 0);
+
+        assert_eq!(
+            game.add_vote(PlayerId(1), vec![0, 1]),
+            Err(GameError::TooManyChoices)
+        );
+        assert!(get_votes(&game, PlayerId(1)).is_empty());
+
+        // The same option chosen twice is still one choice
+        game.add_vote(PlayerId(1), vec![1, 1]).unwrap();
+        assert_eq!(get_votes(&game, PlayerId(1)), vec![PlayerId(2)]);
+    }
+
+    #[test]
+    fn an_option_voted_for_twice_counts_once() {
+        let mut game = start_game(4, &[("start", "day")], 0);
+
+        game.add_vote(PlayerId(1), vec![1, 2, 1]).unwrap();
+        assert_eq!(
+            get_votes(&game, PlayerId(1)),
+            vec![PlayerId(2), PlayerId(3)]
+        );
+    }
+
+    #[test]
+    fn day_chat_reaches_every_other_player() {
+        let mut game = start_game(4, &[("start", "day")], 0);
+
+        let (channel, recipients) = get_relay(&game, PlayerId(1));
+        assert!(matches!(channel, ChatChannel::Day));
+        assert_eq!(recipients, vec![PlayerId(2), PlayerId(3), PlayerId(4)]);
+
+        game.phase = GamePhase::Trial {
+            defendant_id: PlayerId(2),
+            verdicts: HashMap::new(),
+        };
+        let (channel, recipients) = get_relay(&game, PlayerId(2));
+        assert!(matches!(channel, ChatChannel::Day));
+        assert_eq!(recipients, vec![PlayerId(1), PlayerId(3), PlayerId(4)]);
+    }
+
+    #[test]
+    fn mafia_night_chat_only_reaches_the_other_living_mafia() {
+        let mut game = start_game(8, &[], 0);
+        let mafia_ids = get_ids_with_role(&game, Role::Mafia);
+        assert_eq!(mafia_ids.len(), 2);
+
+        let (channel, recipients) = get_relay(&game, mafia_ids[0]);
+        assert!(matches!(channel, ChatChannel::Mafia));
+        assert_eq!(recipients, vec![mafia_ids[1]]);
+
+        kill(&mut game, mafia_ids[1]);
+        assert!(matches!(
+            game.route_chat_message(mafia_ids[0]),
+            ChatRoute::Reject { reason } if reason == "There are no other Mafia left to talk to"
+        ));
+    }
+
+    #[test]
+    fn players_other_than_the_mafia_are_asleep_at_night() {
+        let game = start_game(8, &[], 0);
+        for player in game.players.iter().filter(|p| p.role != Role::Mafia) {
+            assert!(matches!(
+                game.route_chat_message(player.player_id),
+                ChatRoute::Reject { reason } if reason == "You are asleep. Wait for the day to talk"
+            ));
+        }
+    }
+
+    #[test]
+    fn graveyard_chat_only_reaches_dead_players() {
+        let mut game = start_game(5, &[("start", "day")], 0);
+        kill(&mut game, PlayerId(4));
+        assert!(matches!(
+            game.route_chat_message(PlayerId(4)),
+            ChatRoute::Reject { reason } if reason == "Nobody else is in the graveyard yet"
+        ));
+
+        kill(&mut game, PlayerId(5));
+        let (channel, recipients) = get_relay(&game, PlayerId(4));
+        assert!(matches!(channel, ChatChannel::Graveyard));
+        assert_eq!(recipients, vec![PlayerId(5)]);
+
+        // The dead can read the day chat
+        let (_, recipients) = get_relay(&game, PlayerId(1));
+        assert_eq!(
+            recipients,
+            vec![PlayerId(2), PlayerId(3), PlayerId(4), PlayerId(5)]
+        );
+    }
+
+    #[test]
+    fn dead_players_never_reach_living_players() {
+        let mut game = start_game(5, &[], 0);
+        kill(&mut game, PlayerId(4));
+        kill(&mut game, PlayerId(5));
+
+        let phases = [
+            GamePhase::Night {
+                actions: Vec::new(),
+            },
+            GamePhase::Voting {
+                votes: HashMap::new(),
+            },
+            GamePhase::Defense {
+                defendant_id: PlayerId(1),
+            },
+            GamePhase::Trial {
+                defendant_id: PlayerId(1),
+                verdicts: HashMap::new(),
+            },
+            // Only the lynched player's own last words reach the living
+            GamePhase::LastWords {
+                player_id: PlayerId(5),
+            },
+            GamePhase::Ending,
+        ];
+        for phase in phases {
+            game.phase = phase;
+            let (channel, recipients) = get_relay(&game, PlayerId(4));
+            assert!(matches!(channel, ChatChannel::Graveyard));
+            assert_eq!(recipients, vec![PlayerId(5)]);
+        }
+    }
+
+    #[test]
+    fn only_the_detective_learns_their_results_and_sees_them_again_each_night() {
+        let mut game = start_game(5, &[], 0);
+        let mafia_id = get_ids_with_role(&game, Role::Mafia)[0];
+        let doctor_id = get_ids_with_role(&game, Role::Doctor)[0];
+        let detective_id = get_ids_with_role(&game, Role::Detective)[0];
+        let civilian_id = get_ids_with_role(&game, Role::Civilian)[0];
+        let civilian_name = game.get_username(civilian_id).to_string();
+        let mafia_name = game.get_username(mafia_id).to_string();
+
+        game.add_night_action(mafia_id, NOBODY_PLAYER_ID).unwrap();
+        game.add_night_action(doctor_id, doctor_id).unwrap();
+        game.add_night_action(detective_id, civilian_id).unwrap();
+        assert!(game.end_phase().is_some());
+
+        let result = format!("Night 1: {civilian_name} is not a Mafia");
+        let private_messages = game.get_private_messages();
+        assert_eq!(
+            private_messages.get(&detective_id),
+            Some(&vec![format!("Investigation result - {result}")])
+        );
+        assert_eq!(private_messages.len(), 1);
+        assert!(!game.get_transition_message().contains(&civilian_name));
+
+        // Everyone votes for nobody, so that the next night starts
+        let nobody_option = game.get_vote_options().unwrap().len() as i32 - 1;
+        for player_id in (1..=5).map(PlayerId) {
+            game.add_vote(player_id, vec![nobody_option]).unwrap();
+        }
+        assert!(matches!(game.end_phase(), Some(GamePhase::Night { .. })));
+
+        let (prompt, _) = game.get_night_actions().remove(&detective_id).unwrap();
+        assert_eq!(
+            prompt,
+            format!("You are a Detective.\n{result}\nPick a player to investigate:")
+        );
+
+        game.add_night_action(mafia_id, NOBODY_PLAYER_ID).unwrap();
+        game.add_night_action(doctor_id, doctor_id).unwrap();
+        game.add_night_action(detective_id, mafia_id).unwrap();
+        assert!(game.end_phase().is_some());
+        assert_eq!(
+            game.get_private_messages().get(&detective_id),
+            Some(&vec![format!(
+                "Investigation result - Night 2: {mafia_name} is a Mafia"
+            )])
+        );
+        assert_eq!(game.get_investigations(detective_id).len(), 2);
+    }
+
+    #[test]
+    fn the_doctor_saves_the_mafias_target() {
+        let mut game = start_game(4, &[], 0);
+        let mafia_id = get_ids_with_role(&game, Role::Mafia)[0];
+        let doctor_id = get_ids_with_role(&game, Role::Doctor)[0];
+        let target_id = get_ids_with_role(&game, Role::Civilian)[0];
+
+        game.add_night_action(mafia_id, target_id).unwrap();
+        game.add_night_action(doctor_id, target_id).unwrap();
+        assert!(matches!(game.end_phase(), Some(GamePhase::Voting { .. })));
+
+        assert!(game.get_player(target_id).unwrap().is_alive);
+        // The transition message is sent to every living player
+        assert_eq!(
+            game.get_transition_message(),
+            "Nobody died last night\nAn attack was prevented last night"
+        );
+    }
 }
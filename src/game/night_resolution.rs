@@ -0,0 +1,338 @@
+use crate::game::PlayerId;
+use std::collections::HashSet;
+
+use super::*;
+
+/// The order in which night actions are resolved. Actions with a lower priority are resolved first
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ActionPriority {
+    Protect,
+    Kill,
+    Investigate,
+}
+
+impl Action {
+    pub fn priority(&self) -> ActionPriority {
+        match self {
+            Action::Protect { .. } => ActionPriority::Protect,
+            Action::Kill { .. } => ActionPriority::Kill,
+            Action::Investigate { .. } => ActionPriority::Investigate,
+        }
+    }
+}
+
+/// The result of resolving a night's actions
+#[derive(Clone, Default)]
+pub struct NightOutcome {
+    /// Players that died, in the order that they were killed
+    pub killed: Vec<PlayerId>,
+    /// Players that were attacked but protected
+    pub saved: Vec<PlayerId>,
+    /// What each investigating player learned
+    pub investigations: Vec<Investigation>,
+}
+
+/// Returns the Mafia that makes the kill in `TeamKillMode::DesignatedKiller`
+pub fn get_designated_killer(players: &[Player]) -> Option<&Player> {
+    players.iter().find(|p| p.is_alive && p.role == Role::Mafia)
+}
+
+/// Decides the Mafia's one kill from each member's `Action::Kill` pick. Returns `None` if the
+/// Mafia have not agreed on a victim
+pub fn get_team_kill(players: &[Player], actions: &[Action], mode: TeamKillMode) -> Option<Action> {
+    let mut picks = actions.iter().filter_map(|a| match a {
+        Action::Kill { source, target } => Some((*source, *target)),
+        _ => None,
+    });
+
+    match mode {
+        TeamKillMode::DesignatedKiller => {
+            let killer_id = get_designated_killer(players)?.player_id;
+            picks
+                .find(|(source, _)| *source == killer_id)
+                .map(|(source, target)| Action::Kill { source, target })
+        }
+        TeamKillMode::Majority => {
+            // (target, first source to pick the target, pick count), in the order of first pick
+            let mut tally: Vec<(PlayerId, PlayerId, usize)> = Vec::new();
+            for (source, target) in picks {
+                match tally.iter_mut().find(|(t, _, _)| *t == target) {
+                    Some((_, _, count)) => *count += 1,
+                    None => tally.push((target, source, 1)),
+                }
+            }
+
+            let top_count = tally.iter().map(|(_, _, count)| *count).max()?;
+            let mut top_picks = tally.iter().filter(|(_, _, count)| *count == top_count);
+            match (top_picks.next(), top_picks.next()) {
+                (Some((target, source, _)), None) => Some(Action::Kill {
+                    source: *source,
+                    target: *target,
+                }),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Replaces the Mafia's individual kill picks with their one team kill
+pub fn consolidate_team_kill(
+    players: &[Player],
+    actions: &[Action],
+    mode: TeamKillMode,
+) -> Vec<Action> {
+    let team_kill = get_team_kill(players, actions, mode);
+
+    actions
+        .iter()
+        .filter(|a| !matches!(a, Action::Kill { .. }))
+        .cloned()
+        .chain(team_kill)
+        .collect()
+}
+
+/// Resolves `actions` against `players` in order of `Action::priority`. Actions of the same
+/// priority are resolved in the order they were submitted.
+///
+/// A player killed by an earlier action does not get to perform their own action. `players`
+/// is not modified, the caller should apply the returned `NightOutcome`.
+pub fn resolve_night(players: &[Player], actions: &[Action], night: usize) -> NightOutcome {
+    let mut ordered_actions = actions.iter().collect::<Vec<_>>();
+    ordered_actions.sort_by_key(|a| a.priority());
+
+    let find_alive = |player_id: &PlayerId| {
+        players
+            .iter()
+            .find(|p| p.player_id == *player_id && p.is_alive)
+    };
+
+    let mut outcome = NightOutcome::default();
+    let mut protected_ids = HashSet::new();
+    for action in ordered_actions {
+        if outcome.killed.contains(&action.source()) {
+            continue;
+        }
+
+        match action {
+            Action::Protect { target, .. } => {
+                protected_ids.insert(*target);
+            }
+            Action::Kill { target, .. } => {
+                if find_alive(target).is_none() || outcome.killed.contains(target) {
+                    continue;
+                }
+
+                if protected_ids.contains(target) {
+                    if !outcome.saved.contains(target) {
+                        outcome.saved.push(*target);
+                    }
+                } else {
+                    outcome.killed.push(*target);
+                }
+            }
+            Action::Investigate { source, target } => {
+                if let Some(target) = find_alive(target) {
+                    outcome.investigations.push(Investigation {
+                        night,
+                        investigator_id: *source,
+                        target_username: target.username.clone(),
+                        is_mafia: target.role == Role::Mafia,
+                    });
+                }
+            }
+        }
+    }
+
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{game_setup::GameSetup, game_v1::GameV1, Game},
+        lobby_manager::Lobby,
+    };
+
+    /// Player1 is the Mafia and Player2 the Doctor. Every other player is a Civilian
+    fn make_players(count: usize) -> Vec<Player> {
+        let mut roles = vec![Role::Mafia, Role::Doctor];
+        roles.resize(count, Role::Civilian);
+        with_roles(&roles)
+    }
+
+    /// Creates living players Player1, Player2 and so on, with the given roles
+    fn with_roles(roles: &[Role]) -> Vec<Player> {
+        (1..)
+            .zip(roles)
+            .map(|(i, role)| Player {
+                player_id: PlayerId(i),
+                username: format!("Player{i}"),
+                role: *role,
+                is_alive: true,
+                has_left: false,
+            })
+            .collect()
+    }
+
+    /// Players 1 to 3 are the Mafia, followed by 3 Civilians
+    fn make_team() -> Vec<Player> {
+        let mut roles = vec![Role::Mafia; 3];
+        roles.resize(6, Role::Civilian);
+        with_roles(&roles)
+    }
+
+    fn get_target(action: Option<Action>) -> Option<PlayerId> {
+        action.map(|a| a.target())
+    }
+
+    fn kill(source: i64, target: i64) -> Action {
+        Action::Kill {
+            source: PlayerId(source),
+            target: PlayerId(target),
+        }
+    }
+
+    fn protect(source: i64, target: i64) -> Action {
+        Action::Protect {
+            source: PlayerId(source),
+            target: PlayerId(target),
+        }
+    }
+
+    fn investigate(source: i64, target: i64) -> Action {
+        Action::Investigate {
+            source: PlayerId(source),
+            target: PlayerId(target),
+        }
+    }
+
+    #[test]
+    fn protections_resolve_before_kills() {
+        let players = make_players(4);
+        // The kill is submitted first, but the protection still saves the target
+        let outcome = resolve_night(&players, &[kill(1, 3), protect(2, 3)], 1);
+
+        assert!(outcome.killed.is_empty());
+        assert_eq!(outcome.saved, vec![PlayerId(3)]);
+    }
+
+    #[test]
+    fn actions_of_the_same_priority_resolve_in_submission_order() {
+        let players = make_players(5);
+        let outcome = resolve_night(&players, &[kill(1, 4), kill(1, 3), kill(1, 5)], 1);
+        assert_eq!(outcome.killed, vec![PlayerId(4), PlayerId(3), PlayerId(5)]);
+
+        let outcome = resolve_night(&players, &[investigate(3, 1), investigate(4, 2)], 1);
+        let investigator_ids = outcome
+            .investigations
+            .iter()
+            .map(|i| i.investigator_id)
+            .collect::<Vec<_>>();
+        assert_eq!(investigator_ids, vec![PlayerId(3), PlayerId(4)]);
+        assert!(outcome.investigations[0].is_mafia);
+        assert!(!outcome.investigations[1].is_mafia);
+    }
+
+    #[test]
+    fn players_killed_earlier_in_the_night_do_not_act() {
+        let mut players = make_players(4);
+        players[3].role = Role::Detective;
+        let outcome = resolve_night(&players, &[investigate(4, 1), kill(1, 4)], 1);
+
+        assert_eq!(outcome.killed, vec![PlayerId(4)]);
+        assert!(outcome.investigations.is_empty());
+    }
+
+    #[test]
+    fn a_tied_majority_kills_nobody() {
+        let players = make_team();
+        let actions = [kill(1, 4), kill(2, 5)];
+        assert!(get_team_kill(&players, &actions, TeamKillMode::Majority).is_none());
+
+        // Consolidating drops every pick, so nobody dies
+        let actions = consolidate_team_kill(&players, &actions, TeamKillMode::Majority);
+        assert!(resolve_night(&players, &actions, 1).killed.is_empty());
+
+        let actions = [kill(1, 4), kill(2, 5), kill(3, 5)];
+        assert_eq!(
+            get_target(get_team_kill(&players, &actions, TeamKillMode::Majority)),
+            Some(PlayerId(5))
+        );
+    }
+
+    #[test]
+    fn a_changed_pick_replaces_the_earlier_one() {
+        let lobby = Lobby::with_players(7, GameSetup::default());
+        let mut game = GameV1::from_lobby(&lobby, 0);
+        let mafia_ids = game
+            .players
+            .iter()
+            .filter(|p| p.role == Role::Mafia)
+            .map(|p| p.player_id)
+            .collect::<Vec<_>>();
+        let target_ids = game
+            .players
+            .iter()
+            .filter(|p| p.role != Role::Mafia)
+            .map(|p| p.player_id)
+            .collect::<Vec<_>>();
+        assert_eq!(mafia_ids.len(), 1);
+
+        let get_kill = |game: &GameV1| {
+            let GamePhase::Night { actions } = &game.phase else {
+                panic!("The game starts at night");
+            };
+            get_target(get_team_kill(
+                &game.players,
+                actions,
+                TeamKillMode::Majority,
+            ))
+        };
+        game.add_night_action(mafia_ids[0], target_ids[0]).unwrap();
+        assert_eq!(get_kill(&game), Some(target_ids[0]));
+        game.add_night_action(mafia_ids[0], target_ids[1]).unwrap();
+        assert_eq!(get_kill(&game), Some(target_ids[1]));
+    }
+
+    #[test]
+    fn the_designated_killer_ignores_suggestions() {
+        let players = make_team();
+        let actions = [kill(2, 4), kill(3, 4), kill(1, 6)];
+        let team_kill = get_team_kill(&players, &actions, TeamKillMode::DesignatedKiller);
+        assert_eq!(team_kill.as_ref().map(|a| a.source()), Some(PlayerId(1)));
+        assert_eq!(get_target(team_kill), Some(PlayerId(6)));
+
+        // Without the killer's pick, the suggestions kill nobody
+        let actions = [kill(2, 4), kill(3, 4)];
+        assert!(get_team_kill(&players, &actions, TeamKillMode::DesignatedKiller).is_none());
+    }
+
+    #[test]
+    fn the_next_mafia_becomes_the_killer_once_the_killer_dies() {
+        let mut players = make_team();
+        assert_eq!(
+            get_designated_killer(&players).map(|p| p.player_id),
+            Some(PlayerId(1))
+        );
+
+        players[0].is_alive = false;
+        assert_eq!(
+            get_designated_killer(&players).map(|p| p.player_id),
+            Some(PlayerId(2))
+        );
+        let actions = [kill(1, 4), kill(2, 5), kill(3, 6)];
+        assert_eq!(
+            get_target(get_team_kill(
+                &players,
+                &actions,
+                TeamKillMode::DesignatedKiller
+            )),
+            Some(PlayerId(5))
+        );
+
+        players[1].is_alive = false;
+        players[2].is_alive = false;
+        assert!(get_designated_killer(&players).is_none());
+    }
+}
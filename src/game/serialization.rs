@@ -0,0 +1,132 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+/// Version of the JSON format written by `Game::to_json`. Bump it whenever a change to the game
+/// state would stop older JSON from being read back, and teach `migrate` to upgrade the older JSON.
+///
+/// Version 2 names each player's id `player_id` rather than `chat_id`
+pub const GAME_JSON_VERSION: u32 = 2;
+
+/// The top level object of the JSON format, so that readers can check the format before reading
+/// the game itself
+#[derive(Serialize, Deserialize)]
+struct VersionedGame<T> {
+    version: u32,
+    game: T,
+}
+
+pub fn to_versioned_json<T: Serialize>(game: &T) -> serde_json::Result<String> {
+    serde_json::to_string(&VersionedGame {
+        version: GAME_JSON_VERSION,
+        game,
+    })
+}
+
+/// Fails if the JSON was written with a newer `GAME_JSON_VERSION`, or with a version older than
+/// the first one
+pub fn from_versioned_json<T: DeserializeOwned>(json: &str) -> serde_json::Result<T> {
+    let VersionedGame { version, mut game } = serde_json::from_str::<VersionedGame<Value>>(json)?;
+    if !(1..=GAME_JSON_VERSION).contains(&version) {
+        return Err(serde::de::Error::custom(format!(
+            "unsupported game format version {version}, expected {GAME_JSON_VERSION}"
+        )));
+    }
+
+    migrate(version, &mut game);
+    T::deserialize(game)
+}
+
+/// Upgrades a game written with `version` to the current `GAME_JSON_VERSION`, one version at a
+/// time
+fn migrate(version: u32, game: &mut Value) {
+    if version < 2 {
+        if let Some(players) = game.get_mut("players").and_then(Value::as_array_mut) {
+            for player in players.iter_mut().filter_map(Value::as_object_mut) {
+                if let Some(chat_id) = player.remove("chat_id") {
+                    player.insert(String::from("player_id"), chat_id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{game_setup::GameSetup, game_v1::GameV1, Game, GamePhase, Role},
+        lobby_manager::Lobby,
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Saved {
+        night: usize,
+        names: Vec<String>,
+    }
+
+    #[test]
+    fn versioned_json_round_trips() {
+        let saved = Saved {
+            night: 3,
+            names: vec![String::from("Player1"), String::from("Player2")],
+        };
+        let json = to_versioned_json(&saved).unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], GAME_JSON_VERSION);
+        assert_eq!(from_versioned_json::<Saved>(&json).unwrap(), saved);
+    }
+
+    #[test]
+    fn a_game_in_progress_round_trips() {
+        let mut setup = GameSetup::default();
+        setup.set_option("mafia", "1", 6).unwrap();
+        let mut game = GameV1::from_lobby(&Lobby::with_players(6, setup), 0);
+
+        // The Mafia kills one Civilian at night, another quits, and a vote is cast the next day
+        let ids_with_role = |game: &GameV1, role: Role| {
+            game.players
+                .iter()
+                .filter(|p| p.role == role)
+                .map(|p| p.player_id)
+                .collect::<Vec<_>>()
+        };
+        let mafia_id = ids_with_role(&game, Role::Mafia)[0];
+        let civilian_ids = ids_with_role(&game, Role::Civilian);
+        game.add_night_action(mafia_id, civilian_ids[0]).unwrap();
+        game.end_or_force_phase();
+        game.quit(civilian_ids[1]).unwrap();
+        game.add_vote(mafia_id, vec![0]).unwrap();
+        assert!(!game.get_history().is_empty());
+
+        let to_value = |game: &dyn Game| -> serde_json::Value {
+            serde_json::from_str(&game.snapshot().to_json().unwrap()).unwrap()
+        };
+        let restored = GameV1::from_json(&game.to_json().unwrap()).unwrap();
+        assert_eq!(to_value(&restored), to_value(&game));
+        assert!(matches!(
+            restored.get_phase(),
+            GamePhase::Voting { votes } if votes.contains_key(&mafia_id)
+        ));
+        assert!(matches!(
+            &restored.get_history()[0].phase,
+            GamePhase::Night { actions } if actions.len() == 1
+        ));
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let json = format!(
+            r#"{{"version":{},"game":{{"night":3,"names":[]}}}}"#,
+            GAME_JSON_VERSION + 1
+        );
+        let err = from_versioned_json::<Saved>(&json).unwrap_err();
+        assert!(err.to_string().contains(&format!(
+            "unsupported game format version {}",
+            GAME_JSON_VERSION + 1
+        )));
+
+        // JSON from before the format was versioned has no version at all
+        assert!(from_versioned_json::<Saved>(r#"{"night":3,"names":[]}"#).is_err());
+    }
+}
@@ -1,22 +1,50 @@
-use teloxide::types::ChatId;
+use crate::game::PlayerId;
 
-use crate::game::Game;
+use crate::game::{Game, GameEvent};
+use game_actor::GameHandle;
+use local_game_manager::GameId;
 
+/// Keeps track of the games in progress. Each game runs on its own task, reached through a
+/// `GameHandle`, so that games never wait on each other
 pub trait GameManager: Send + Sync {
-    /// Returns a mutable reference to the chat_id's game, if present
-    fn get_player_game(&mut self, chat_id: ChatId) -> Option<&mut Box<dyn Game>>;
+    /// Returns a handle to the player_id's game, if present
+    fn get_player_game(&self, player_id: PlayerId) -> Option<GameHandle>;
 
-    // Adds game to the map
-    fn add_game(&mut self, game: Box<dyn Game>);
+    /// Returns every game in progress
+    fn get_games(&self) -> Vec<GameHandle>;
 
-    // Removes game for the chat_id from the map
-    fn remove_game(&mut self, chat_id: ChatId) -> Option<Box<dyn Game>>;
+    /// Starts running the game on its own task
+    fn add_game(&self, game: Box<dyn Game>) -> GameHandle;
 
-    // If the host quits, then a remaining player should be randomly chosen to be the new host
-    fn quit_game(&mut self, chat_id: ChatId) -> Result<&mut dyn Game, &'static str>;
+    /// Removes the player_id's game from the map. The returned handle should be stopped to end
+    /// the game's task
+    fn remove_game(&self, player_id: PlayerId) -> Option<GameHandle>;
+
+    /// Removes the player_id from their game's players, returning a handle to the game. The game
+    /// itself keeps running for the remaining players
+    fn quit_game(&self, player_id: PlayerId) -> Result<GameHandle, &'static str>;
+
+    /// Moves the player_id's place in their game to the substitute_id, who must not be in a game
+    fn substitute_player(
+        &self,
+        player_id: PlayerId,
+        substitute_id: PlayerId,
+    ) -> Result<GameHandle, &'static str>;
+}
+
+/// Where a game's task keeps its game's events, e.g. a database. Replaying the stored events
+/// restores the game. The methods are called from the games' tasks, so they should not block
+pub trait GameStore: Send + Sync {
+    /// Called by the game's task after every update, with the events recorded since the last one
+    fn append_events(&self, game_id: GameId, events: &[GameEvent]) -> Result<(), &'static str>;
+
+    /// Called by the game's task when it is stopped
+    fn delete_game(&self, game_id: GameId) -> Result<(), &'static str>;
 }
 
+pub mod game_actor;
 pub mod local_game_manager;
+pub mod sqlite_game_manager;
 
 //game_manager keeps track of games progress and player roles (data)
 //game_handler handles replies and prompts. Also decides which prompts to give out (logic)
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+use super::{local_game_manager::GameId, GameStore};
+use crate::game::Game;
+
+/// How many commands can be queued for a game before senders wait
+const COMMAND_BUFFER: usize = 64;
+
+/// A function run on the game's task
+type GameFn = Box<dyn FnOnce(&mut dyn Game) + Send>;
+
+enum GameCommand {
+    Run(GameFn),
+    /// Runs the function, then appends the game's new events to the store
+    Update(GameFn),
+    Stop(oneshot::Sender<Box<dyn Game>>),
+}
+
+/// A handle to a game running on its own task. Commands sent through it run one at a time, in the
+/// order they were sent. Cloning the handle is cheap
+#[derive(Clone)]
+pub struct GameHandle {
+    game_id: GameId,
+    sender: mpsc::Sender<GameCommand>,
+}
+
+/// Starts a task that owns the game and runs the commands sent to the returned handle
+pub fn spawn_game(
+    game_id: GameId,
+    mut game: Box<dyn Game>,
+    store: Option<Arc<dyn GameStore>>,
+) -> GameHandle {
+    let (sender, mut receiver) = mpsc::channel(COMMAND_BUFFER);
+
+    tokio::spawn(async move {
+        // Events recorded before the game was spawned, e.g. its start, are stored first
+        save_events(store.as_deref(), game_id, game.as_mut());
+        while let Some(command) = receiver.recv().await {
+            match command {
+                GameCommand::Run(f) => f(game.as_mut()),
+                GameCommand::Update(f) => {
+                    f(game.as_mut());
+                    save_events(store.as_deref(), game_id, game.as_mut());
+                }
+                GameCommand::Stop(reply) => {
+                    if let Some(store) = &store {
+                        if let Err(err) = store.delete_game(game_id) {
+                            log::error!("{err}");
+                        }
+                    }
+                    reply.send(game).ok();
+                    return;
+                }
+            }
+        }
+    });
+
+    GameHandle { game_id, sender }
+}
+
+/// Appends the events the game recorded since they were last taken to the store, if any
+fn save_events(store: Option<&dyn GameStore>, game_id: GameId, game: &mut dyn Game) {
+    let events = game.take_events();
+    if let (Some(store), false) = (store, events.is_empty()) {
+        if let Err(err) = store.append_events(game_id, &events) {
+            log::error!("{err}");
+        }
+    }
+}
+
+impl GameHandle {
+    pub fn get_game_id(&self) -> GameId {
+        self.game_id
+    }
+
+    async fn send<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut dyn Game) -> R + Send + 'static,
+        make_command: fn(GameFn) -> GameCommand,
+    ) -> Result<R, &'static str> {
+        let (reply, result) = oneshot::channel();
+        let command = make_command(Box::new(move |game: &mut dyn Game| {
+            reply.send(f(game)).ok();
+        }));
+
+        self.sender
+            .send(command)
+            .await
+            .map_err(|_| "Game has ended")?;
+        result.await.map_err(|_| "Game has ended")
+    }
+
+    /// Runs `f` on the game's task without saving the game, e.g. to read its state
+    pub async fn run<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut dyn Game) -> R + Send + 'static,
+    ) -> Result<R, &'static str> {
+        self.send(f, GameCommand::Run).await
+    }
+
+    /// Runs `f` on the game's task, then stores the events it recorded. Should be used for every
+    /// change
+    pub async fn update<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut dyn Game) -> R + Send + 'static,
+    ) -> Result<R, &'static str> {
+        self.send(f, GameCommand::Update).await
+    }
+
+    /// Ends the game's task once the commands sent before have run, deleting the game from the
+    /// store and returning it
+    pub async fn stop(&self) -> Result<Box<dyn Game>, &'static str> {
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(GameCommand::Stop(reply))
+            .await
+            .map_err(|_| "Game has ended")?;
+        result.await.map_err(|_| "Game has ended")
+    }
+}
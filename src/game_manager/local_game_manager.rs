@@ -1,59 +1,136 @@
-use rand::Rng;
-use std::collections::HashMap;
-use teloxide::types::ChatId;
+use crate::game::PlayerId;
+use rand::{rngs::StdRng, Rng};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
 
-use super::GameManager;
+use super::{
+    game_actor::{spawn_game, GameHandle},
+    GameManager, GameStore,
+};
 use crate::game::Game;
 
 #[derive(Eq, Hash, PartialEq, Copy, Clone, derive_more::Display)]
 pub struct GameId(pub i32);
 
+#[derive(Default)]
+struct Registry {
+    games: HashMap<GameId, GameHandle>,
+    player_map: HashMap<PlayerId, GameId>,
+}
+
+/// A `GameManager` that runs each game on its own task. The registry is only locked to look up or
+/// change which games exist, never while a game is being played
 pub struct LocalGameManager {
-    games: HashMap<GameId, Box<dyn Game>>,
-    player_map: HashMap<ChatId, GameId>,
+    registry: RwLock<Registry>,
+    rng: Mutex<StdRng>,
+    store: Option<Arc<dyn GameStore>>,
 }
 
 impl LocalGameManager {
-    pub fn new() -> LocalGameManager {
+    /// Creates a manager that only keeps games in memory, whose game ids are drawn from `rng`,
+    /// e.g. a seeded one
+    pub fn with_rng(rng: StdRng) -> LocalGameManager {
         LocalGameManager {
-            games: HashMap::new(),
-            player_map: HashMap::new(),
+            registry: RwLock::new(Registry::default()),
+            rng: Mutex::new(rng),
+            store: None,
         }
     }
-}
 
-impl GameManager for LocalGameManager {
-    fn get_player_game(&mut self, chat_id: ChatId) -> Option<&mut Box<dyn Game>> {
-        let game_id = self.player_map.get(&chat_id)?;
-        self.games.get_mut(game_id)
+    /// Creates a manager whose games append their events to `store` after every update
+    pub fn with_store(rng: StdRng, store: Arc<dyn GameStore>) -> LocalGameManager {
+        LocalGameManager {
+            store: Some(store),
+            ..LocalGameManager::with_rng(rng)
+        }
     }
 
-    fn add_game(&mut self, game: Box<dyn Game>) {
-        let mut rng = rand::thread_rng();
-        let mut game_id = GameId(rng.gen_range(1_000..10_000));
-        while let Some(_) = self.games.get(&game_id) {
-            game_id = GameId(rng.gen_range(1_000..10_000));
-        }
+    /// Starts running a game under a known game_id, e.g. when restoring a saved game
+    pub fn insert_game(&self, game_id: GameId, game: Box<dyn Game>) -> GameHandle {
+        let mut registry = self.registry.write().unwrap();
+        self.spawn_in(&mut registry, game_id, game)
+    }
 
+    fn spawn_in(
+        &self,
+        registry: &mut Registry,
+        game_id: GameId,
+        game: Box<dyn Game>,
+    ) -> GameHandle {
         for p in game.get_players() {
-            self.player_map.insert(p.chat_id, game_id);
+            registry.player_map.insert(p.player_id, game_id);
         }
+        let handle = spawn_game(game_id, game, self.store.clone());
+        registry.games.insert(game_id, handle.clone());
 
-        self.games.insert(game_id, game);
+        handle
     }
+}
 
-    fn remove_game(&mut self, chat_id: ChatId) -> Option<Box<dyn Game>> {
-        let game_id = self.player_map.get(&chat_id)?;
-        self.games.remove(game_id)
+impl GameManager for LocalGameManager {
+    fn get_player_game(&self, player_id: PlayerId) -> Option<GameHandle> {
+        let registry = self.registry.read().unwrap();
+        let game_id = registry.player_map.get(&player_id)?;
+        registry.games.get(game_id).cloned()
     }
 
-    fn quit_game(&mut self, chat_id: ChatId) -> Result<&mut dyn Game, &'static str> {
-        match self.player_map.get(&chat_id) {
-            Some(game_id) => {
-                let game = self.games.get_mut(game_id).unwrap();
+    fn get_games(&self) -> Vec<GameHandle> {
+        self.registry
+            .read()
+            .unwrap()
+            .games
+            .values()
+            .cloned()
+            .collect()
+    }
 
-                self.player_map.remove(&chat_id);
-                return Ok(game.as_mut());
+    fn add_game(&self, game: Box<dyn Game>) -> GameHandle {
+        let mut registry = self.registry.write().unwrap();
+        let game_id = {
+            let mut rng = self.rng.lock().unwrap();
+            let mut game_id = GameId(rng.gen_range(1_000..10_000));
+            while registry.games.contains_key(&game_id) {
+                game_id = GameId(rng.gen_range(1_000..10_000));
+            }
+            game_id
+        };
+
+        // The game's task stores the events of the game's start
+        self.spawn_in(&mut registry, game_id, game)
+    }
+
+    fn remove_game(&self, player_id: PlayerId) -> Option<GameHandle> {
+        let mut registry = self.registry.write().unwrap();
+        let game_id = *registry.player_map.get(&player_id)?;
+        let handle = registry.games.remove(&game_id)?;
+        registry.player_map.retain(|_, id| *id != game_id);
+
+        Some(handle)
+    }
+
+    fn quit_game(&self, player_id: PlayerId) -> Result<GameHandle, &'static str> {
+        let mut registry = self.registry.write().unwrap();
+        match registry.player_map.remove(&player_id) {
+            Some(game_id) => Ok(registry.games[&game_id].clone()),
+            None => Err("Player not in a game"),
+        }
+    }
+
+    fn substitute_player(
+        &self,
+        player_id: PlayerId,
+        substitute_id: PlayerId,
+    ) -> Result<GameHandle, &'static str> {
+        let mut registry = self.registry.write().unwrap();
+        if registry.player_map.contains_key(&substitute_id) {
+            return Err("The substitute is already in a game");
+        }
+        match registry.player_map.remove(&player_id) {
+            Some(game_id) => {
+                registry.player_map.insert(substitute_id, game_id);
+                Ok(registry.games[&game_id].clone())
             }
             None => Err("Player not in a game"),
         }
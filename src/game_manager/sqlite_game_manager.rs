@@ -0,0 +1,183 @@
+use crate::game::PlayerId;
+use rand::rngs::StdRng;
+use rusqlite::{params, Connection};
+use std::{
+    collections::BTreeMap,
+    sync::{mpsc, Arc},
+    thread,
+};
+
+use super::{
+    game_actor::GameHandle,
+    local_game_manager::{GameId, LocalGameManager},
+    GameManager, GameStore,
+};
+use crate::game::{game_v1::GameV1, Game, GameEvent};
+
+/// A `GameManager` whose games append their events to a SQLite database, so that games in
+/// progress survive a restart
+pub struct SqliteGameManager {
+    games: LocalGameManager,
+}
+
+/// The `game_events` table, shared by the tasks of every game. Writes are queued for a single
+/// writer thread that owns the connection, so that no game's task waits on the database
+struct SqliteGameStore {
+    sender: mpsc::Sender<StoreCommand>,
+}
+
+enum StoreCommand {
+    Append(GameId, Vec<String>),
+    Delete(GameId),
+}
+
+impl SqliteGameManager {
+    /// Opens the database at `path`, restoring the games that were saved in it by replaying their
+    /// events. New game ids are drawn from `rng`. Must be called from within a tokio runtime,
+    /// which the games run on
+    pub fn open(path: &str, rng: StdRng) -> rusqlite::Result<SqliteGameManager> {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS game_events (
+                event_id INTEGER PRIMARY KEY,
+                game_id INTEGER NOT NULL,
+                event TEXT NOT NULL
+            )",
+            (),
+        )?;
+
+        let mut events = BTreeMap::<i32, Vec<String>>::new();
+        {
+            let mut stmt =
+                conn.prepare("SELECT game_id, event FROM game_events ORDER BY event_id")?;
+            let rows = stmt.query_map((), |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (game_id, event) = row?;
+                events.entry(game_id).or_default().push(event);
+            }
+        }
+
+        let store = SqliteGameStore::spawn(conn);
+        let games = LocalGameManager::with_store(rng, Arc::new(store));
+        for (game_id, events) in events {
+            match restore_game(&events) {
+                Ok(game) => {
+                    games.insert_game(GameId(game_id), Box::new(game));
+                }
+                Err(err) => log::error!("Failed to restore game {game_id}: {err}"),
+            }
+        }
+
+        Ok(SqliteGameManager { games })
+    }
+}
+
+/// Rebuilds a game by replaying its events
+fn restore_game(events: &[String]) -> Result<GameV1, String> {
+    let events = events
+        .iter()
+        .map(|event| serde_json::from_str::<GameEvent>(event))
+        .collect::<serde_json::Result<Vec<_>>>()
+        .map_err(|err| err.to_string())?;
+
+    GameV1::replay(&events)
+}
+
+impl SqliteGameStore {
+    /// Starts the writer thread, which runs until every sender has been dropped
+    fn spawn(mut conn: Connection) -> SqliteGameStore {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for command in receiver {
+                let res = match command {
+                    StoreCommand::Append(game_id, events) => {
+                        append_events(&mut conn, game_id, &events)
+                    }
+                    StoreCommand::Delete(game_id) => delete_game(&conn, game_id),
+                };
+                if let Err(err) = res {
+                    log::error!("{err}");
+                }
+            }
+        });
+
+        SqliteGameStore { sender }
+    }
+}
+
+fn append_events(
+    conn: &mut Connection,
+    game_id: GameId,
+    events: &[String],
+) -> Result<(), &'static str> {
+    let tx = conn.transaction().map_err(|_| "Failed to save game")?;
+    for event in events {
+        tx.execute(
+            "INSERT INTO game_events (game_id, event) VALUES (?1, ?2)",
+            params![game_id.0, event],
+        )
+        .map_err(|_| "Failed to save game")?;
+    }
+    tx.commit().map_err(|_| "Failed to save game")
+}
+
+fn delete_game(conn: &Connection, game_id: GameId) -> Result<(), &'static str> {
+    conn.execute(
+        "DELETE FROM game_events WHERE game_id = ?1",
+        params![game_id.0],
+    )
+    .map_err(|_| "Failed to delete game")?;
+
+    Ok(())
+}
+
+impl GameStore for SqliteGameStore {
+    fn append_events(&self, game_id: GameId, events: &[GameEvent]) -> Result<(), &'static str> {
+        let events = events
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<serde_json::Result<Vec<_>>>()
+            .map_err(|_| "Failed to serialize game")?;
+        self.sender
+            .send(StoreCommand::Append(game_id, events))
+            .map_err(|_| "Failed to save game")
+    }
+
+    fn delete_game(&self, game_id: GameId) -> Result<(), &'static str> {
+        self.sender
+            .send(StoreCommand::Delete(game_id))
+            .map_err(|_| "Failed to delete game")
+    }
+}
+
+impl GameManager for SqliteGameManager {
+    fn get_player_game(&self, player_id: PlayerId) -> Option<GameHandle> {
+        self.games.get_player_game(player_id)
+    }
+
+    fn get_games(&self) -> Vec<GameHandle> {
+        self.games.get_games()
+    }
+
+    fn add_game(&self, game: Box<dyn Game>) -> GameHandle {
+        self.games.add_game(game)
+    }
+
+    fn remove_game(&self, player_id: PlayerId) -> Option<GameHandle> {
+        self.games.remove_game(player_id)
+    }
+
+    fn quit_game(&self, player_id: PlayerId) -> Result<GameHandle, &'static str> {
+        self.games.quit_game(player_id)
+    }
+
+    fn substitute_player(
+        &self,
+        player_id: PlayerId,
+        substitute_id: PlayerId,
+    ) -> Result<GameHandle, &'static str> {
+        self.games.substitute_player(player_id, substitute_id)
+    }
+}
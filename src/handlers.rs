@@ -1,22 +1,140 @@
-use std::sync::{Arc, Mutex};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use teloxide::types::{ChatId, MessageId, Recipient, UserId};
+use tokio::time::Instant;
 
 use crate::{
-    game_manager::{local_game_manager::LocalGameManager, GameManager},
-    lobby_manager::{local_lobby_manager::LocalLobbyManager, LobbyManager},
+    game::PlayerId,
+    game_manager::{
+        local_game_manager::GameId, sqlite_game_manager::SqliteGameManager, GameManager,
+    },
+    lobby_manager::{sqlite_lobby_manager::SqliteLobbyManager, LobbyManager},
 };
 
+/// State shared by every handler. Games run on their own tasks behind `game_manager`, so the locks
+/// here are only held briefly and never across an `.await`
 pub struct BotState<L: LobbyManager, G: GameManager> {
-    pub lobby_manager: L,
+    pub lobby_manager: Mutex<L>,
     pub game_manager: G,
+    pub phase_durations: PhaseDurations,
+    /// The poll each player has yet to answer in the current phase, by the chat it was sent to
+    pub poll_msg_ids: Mutex<HashMap<ChatId, MessageId>>,
+    /// The player each current poll was sent to and the phase number it was sent in, by poll id.
+    /// Answers to any other poll, e.g. one sent before a restart, are ignored
+    pub poll_phases: Mutex<HashMap<String, (PlayerId, usize)>>,
+    /// The players whose place in their game can be taken over, by the code given to their
+    /// substitute
+    pub substitute_codes: Mutex<HashMap<i32, PlayerId>>,
+    /// The deadline of each game's current phase. A paused game has no deadline, and a phase is
+    /// only forced to end by the task waiting for the game's latest deadline
+    pub deadlines: Mutex<HashMap<GameId, Instant>>,
+    /// Draws the seed of each new game
+    pub rng: Mutex<StdRng>,
+}
+
+// Players are identified by the id of their private chat with the bot
+impl From<ChatId> for PlayerId {
+    fn from(chat_id: ChatId) -> Self {
+        PlayerId(chat_id.0)
+    }
+}
+
+impl From<UserId> for PlayerId {
+    fn from(user_id: UserId) -> Self {
+        ChatId::from(user_id).into()
+    }
+}
+
+impl From<PlayerId> for ChatId {
+    fn from(player_id: PlayerId) -> Self {
+        ChatId(player_id.0)
+    }
+}
+
+impl From<PlayerId> for Recipient {
+    fn from(player_id: PlayerId) -> Self {
+        Recipient::Id(player_id.into())
+    }
 }
 
-pub type AsyncBotState = Arc<Mutex<BotState<LocalLobbyManager, LocalGameManager>>>;
+/// How long each phase may last before it is forced to end
+#[derive(Clone, Copy)]
+pub struct PhaseDurations {
+    pub night: Duration,
+    pub voting: Duration,
+    /// How long the defendant may speak before their trial, if the setup allows it
+    pub defense: Duration,
+    pub trial: Duration,
+    /// How long a lynched player may speak, if the setup allows it
+    pub last_words: Duration,
+    /// How long before the deadline idle players are warned
+    pub warning: Duration,
+}
+
+impl PhaseDurations {
+    /// Reads the durations, in seconds, from the environment. Unset variables fall back to defaults
+    pub fn from_env() -> PhaseDurations {
+        let read_secs = |key: &str, default: u64| {
+            let secs = env::var(key)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(default);
+            Duration::from_secs(secs)
+        };
+
+        PhaseDurations {
+            night: read_secs("NIGHT_DURATION_SECS", 120),
+            voting: read_secs("VOTING_DURATION_SECS", 120),
+            defense: read_secs("DEFENSE_DURATION_SECS", 60),
+            trial: read_secs("TRIAL_DURATION_SECS", 60),
+            last_words: read_secs("LAST_WORDS_DURATION_SECS", 30),
+            warning: read_secs("DEADLINE_WARNING_SECS", 30),
+        }
+    }
+}
+
+pub type AsyncBotState = Arc<BotState<SqliteLobbyManager, SqliteGameManager>>;
 
+/// Creates the bot state, restoring the lobbies and games saved in the database at `DATABASE_PATH`.
+/// If `RNG_SEED` is set, lobby codes, game ids and games are drawn from it
 pub fn new_async_bot_state() -> AsyncBotState {
-    Arc::new(Mutex::new(BotState {
-        lobby_manager: LocalLobbyManager::new(),
-        game_manager: LocalGameManager::new(),
-    }))
+    let database_path = env::var("DATABASE_PATH").unwrap_or(String::from("that_mafia_game.db"));
+    let rng = match env::var("RNG_SEED").ok().and_then(|seed| seed.parse().ok()) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    open_async_bot_state(&database_path, PhaseDurations::from_env(), rng)
+}
+
+/// Creates the bot state, restoring the lobbies and games saved in the database at
+/// `database_path`. Lobby codes, game ids and games are drawn from `rng`. Must be called from
+/// within a tokio runtime, which the restored games run on
+pub fn open_async_bot_state(
+    database_path: &str,
+    phase_durations: PhaseDurations,
+    mut rng: StdRng,
+) -> AsyncBotState {
+    let mut split_rng = || StdRng::seed_from_u64(rng.gen());
+
+    Arc::new(BotState {
+        lobby_manager: Mutex::new(
+            SqliteLobbyManager::open(database_path, split_rng()).expect("Failed to open database"),
+        ),
+        game_manager: SqliteGameManager::open(database_path, split_rng())
+            .expect("Failed to open database"),
+        phase_durations,
+        poll_msg_ids: Mutex::new(HashMap::new()),
+        poll_phases: Mutex::new(HashMap::new()),
+        substitute_codes: Mutex::new(HashMap::new()),
+        deadlines: Mutex::new(HashMap::new()),
+        rng: Mutex::new(split_rng()),
+    })
 }
 
 pub mod game_handler;
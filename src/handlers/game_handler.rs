@@ -1,14 +1,19 @@
-use std::collections::HashMap;
+use rand::Rng;
 use teloxide::{
     dispatching::UpdateFilterExt,
     prelude::*,
     types::{InlineKeyboardButton, InlineKeyboardMarkup},
+    utils::command::BotCommands,
     RequestError,
 };
-use tokio::task::JoinSet;
+use tokio::{task::JoinSet, time::Instant};
 
 use super::AsyncBotState;
-use crate::{game::*, game_manager::GameManager};
+use crate::{
+    game::*,
+    game_manager::{game_actor::GameHandle, GameManager},
+    lobby_manager::User,
+};
 
 pub fn get_game_handler() -> Handler<
     'static,
@@ -16,84 +21,680 @@ pub fn get_game_handler() -> Handler<
     Result<(), teloxide::RequestError>,
     teloxide::dispatching::DpHandlerDescription,
 > {
-    let is_night_action = |q: CallbackQuery, bot_state: AsyncBotState| {
-        let mut state_lock = bot_state.lock().unwrap();
-        let opt = state_lock.game_manager.get_player_game(q.from.id.into());
-
-        if let Some(game) = opt {
-            matches!(game.get_phase(), GamePhase::Night { .. })
-        } else {
-            false
-        }
+    let is_night_action = |q: CallbackQuery, bot_state: AsyncBotState| async move {
+        is_in_phase(&bot_state, q.from.id.into(), |phase| {
+            matches!(phase, GamePhase::Night { .. })
+        })
+        .await
     };
-    let is_voting_vote = |bot_state: AsyncBotState, poll_answer: PollAnswer| {
-        let mut state_lock = bot_state.lock().unwrap();
-        let opt = state_lock
-            .game_manager
-            .get_player_game(poll_answer.user.id.into());
-
-        if let Some(game) = opt {
-            matches!(game.get_phase(), GamePhase::Voting { .. })
-        } else {
-            false
-        }
+    let is_voting_vote = |bot_state: AsyncBotState, poll_answer: PollAnswer| async move {
+        is_in_phase(&bot_state, poll_answer.user.id.into(), |phase| {
+            matches!(phase, GamePhase::Voting { .. })
+        })
+        .await
     };
-    let is_trial_verdict = |bot_state: AsyncBotState, poll_answer: PollAnswer| {
-        let mut state_lock = bot_state.lock().unwrap();
-        let opt = state_lock
-            .game_manager
-            .get_player_game(poll_answer.user.id.into());
-
-        if let Some(game) = opt {
-            matches!(game.get_phase(), GamePhase::Trial { .. })
-        } else {
-            false
-        }
+    let is_trial_verdict = |bot_state: AsyncBotState, poll_answer: PollAnswer| async move {
+        is_in_phase(&bot_state, poll_answer.user.id.into(), |phase| {
+            matches!(phase, GamePhase::Trial { .. })
+        })
+        .await
     };
 
     let is_in_game = |msg: Message, bot_state: AsyncBotState| {
         bot_state
-            .lock()
-            .unwrap()
             .game_manager
-            .get_player_game(msg.chat.id)
+            .get_player_game(msg.chat.id.into())
             .is_some()
     };
 
     dptree::entry()
         .branch(
             Update::filter_callback_query()
-                .branch(dptree::filter(is_night_action).endpoint(handle_night)),
+                .branch(dptree::filter_async(is_night_action).endpoint(handle_night)),
         )
         .branch(
             Update::filter_poll_answer()
-                .filter(is_voting_vote)
+                .filter_async(is_voting_vote)
                 .endpoint(handle_vote),
         )
         .branch(
             Update::filter_poll_answer()
-                .filter(is_trial_verdict)
+                .filter_async(is_trial_verdict)
                 .endpoint(handle_trial),
         )
         .branch(
             Update::filter_message()
                 .filter(is_in_game)
-                .endpoint(no_response_handler),
+                .filter_command::<InGameCommand>()
+                .endpoint(handle_command),
+        )
+        .branch(
+            Update::filter_message()
+                .filter(is_in_game)
+                .endpoint(handle_chat),
         )
 }
 
-async fn no_response_handler() -> Result<(), RequestError> {
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "In-game commands")]
+enum InGameCommand {
+    #[command(description = "Shows this message.")]
+    Help,
+    #[command(description = "Leave the game. If you are alive, you die")]
+    Quit,
+    #[command(description = "Get a code that lets someone else take over your place and role")]
+    Substitute,
+    #[command(description = "List the players in the game")]
+    Players,
+    #[command(description = "Kill the player with the number shown by /players (host only)")]
+    Modkill { number: String },
+    #[command(description = "Pause the game, stopping its timer and choices (host only)")]
+    Pause,
+    #[command(description = "Resume the paused game, restarting its timer (host only)")]
+    Resume,
+    #[command(description = "End the current phase with the choices made so far (host only)")]
+    Next,
+}
+
+async fn handle_command(
+    bot_state: AsyncBotState,
+    bot: Bot,
+    msg: Message,
+    cmd: InGameCommand,
+) -> Result<(), RequestError> {
+    let player_id = PlayerId::from(msg.chat.id);
+    let text = match cmd {
+        InGameCommand::Help => InGameCommand::descriptions().to_string(),
+        InGameCommand::Quit => match quit_game(player_id, bot.clone(), bot_state).await {
+            Ok(()) => String::from("You have left the game"),
+            Err(message) => format!("Encountered error: {}", message),
+        },
+        InGameCommand::Substitute => match get_snapshot(&bot_state, player_id).await {
+            Ok(game)
+                if game
+                    .get_players()
+                    .iter()
+                    .any(|p| p.player_id == player_id && p.is_alive()) =>
+            {
+                let code = bot_state.rng.lock().unwrap().gen_range(10_000..100_000);
+                let mut substitute_codes = bot_state.substitute_codes.lock().unwrap();
+                // Only the latest code works, and substitute codes never look like lobby codes
+                substitute_codes.retain(|_, id| *id != player_id);
+                substitute_codes.insert(code, player_id);
+                format!(
+                    "Ask your substitute to send me:\n/join {code}\n\nThey will take over your \
+                    place and role, and you will leave the game"
+                )
+            }
+            Ok(_) => String::from("Encountered error: Only living players can be substituted"),
+            Err(message) => format!("Encountered error: {}", message),
+        },
+        InGameCommand::Players => match get_snapshot(&bot_state, player_id).await {
+            Ok(game) => game
+                .get_players()
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    let mut line = format!("{}. {}", i + 1, p.username);
+                    if game.get_host_id() == Some(p.player_id) {
+                        line.push_str(" (host)");
+                    }
+                    if !p.is_alive() {
+                        line.push_str(" (dead)");
+                    }
+                    line
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(message) => format!("Encountered error: {}", message),
+        },
+        InGameCommand::Modkill { .. }
+        | InGameCommand::Pause
+        | InGameCommand::Resume
+        | InGameCommand::Next => {
+            match moderate_game(cmd, player_id, bot.clone(), bot_state).await {
+                Ok(()) => return Ok(()),
+                Err(message) => format!("Encountered error: {}", message),
+            }
+        }
+    };
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
+
+/// Carries out one of the host's commands. Every player is told what the host did
+async fn moderate_game(
+    cmd: InGameCommand,
+    host_id: PlayerId,
+    bot: Bot,
+    bot_state: AsyncBotState,
+) -> Result<(), &'static str> {
+    let game = bot_state
+        .game_manager
+        .get_player_game(host_id)
+        .ok_or("Player is not in a game")?;
+    let snapshot = game.run(|game| game.snapshot()).await?;
+    if snapshot.get_host_id() != Some(host_id) {
+        return Err("Only the host can do that");
+    }
+    if matches!(snapshot.get_phase(), GamePhase::Ending) {
+        return Err("The game has already ended");
+    }
+    let announce = |text: String| {
+        let bot = bot.clone();
+        let player_ids = snapshot
+            .get_players()
+            .iter()
+            .map(|p| p.player_id)
+            .collect::<Vec<_>>();
+        async move {
+            for player_id in player_ids {
+                if let Err(err) = bot.send_message(player_id, text.clone()).await {
+                    log::error!("{err}");
+                }
+            }
+        }
+    };
+
+    match cmd {
+        InGameCommand::Modkill { number } => {
+            let target = number
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| snapshot.get_players().get(n.checked_sub(1)?).copied())
+                .filter(|p| p.is_alive())
+                .ok_or("There is no living player with that number. See /players")?;
+            let (target_id, death_name) = (
+                target.player_id,
+                snapshot.get_setup().get_death_name(target),
+            );
+
+            let (game, phase_opt) = game
+                .update(move |game| {
+                    let phase_opt = game.modkill(target_id)?.cloned();
+                    let phase_opt = phase_opt.or_else(|| game.end_phase().cloned());
+                    Ok::<_, GameError>((game.snapshot(), phase_opt))
+                })
+                .await?
+                .map_err(|_| "That player is no longer alive")?;
+
+            let poll_opt = bot_state
+                .poll_msg_ids
+                .lock()
+                .unwrap()
+                .remove(&target_id.into());
+            if let Some(message_id) = poll_opt {
+                if let Err(err) = bot.stop_poll(target_id, message_id).await {
+                    log::error!("{err}");
+                }
+            }
+            announce(format!(
+                "{death_name} was removed from the game by the host"
+            ))
+            .await;
+
+            match phase_opt {
+                Some(phase) => start_next_phase(Some(phase), host_id, bot, bot_state).await,
+                None => {
+                    // Welcome the player to the graveyard
+                    let message_opt = game
+                        .get_private_messages()
+                        .remove(&target_id)
+                        .and_then(|mut messages| messages.pop());
+                    if let Some(text) = message_opt {
+                        if let Err(err) = bot.send_message(target_id, text).await {
+                            log::error!("{err}");
+                        }
+                    }
+                    if matches!(game.get_phase(), GamePhase::Voting { .. }) {
+                        resend_prompts(game.as_ref(), &bot, &bot_state).await?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+        InGameCommand::Pause | InGameCommand::Resume => {
+            let paused = matches!(cmd, InGameCommand::Pause);
+            if snapshot.is_paused() == paused {
+                return Err(if paused {
+                    "The game is already paused"
+                } else {
+                    "The game is not paused"
+                });
+            }
+            let game_snapshot = game
+                .update(move |game| game.set_paused(paused).map(|()| game.snapshot()))
+                .await?
+                .map_err(|_| "The game has already ended")?;
+
+            if paused {
+                bot_state
+                    .deadlines
+                    .lock()
+                    .unwrap()
+                    .remove(&game.get_game_id());
+                announce(String::from(
+                    "The host has paused the game. Choices can be made again once it is resumed",
+                ))
+                .await;
+                Ok(())
+            } else {
+                announce(String::from(
+                    "The host has resumed the game, and the phase's timer has started again",
+                ))
+                .await;
+                spawn_phase_deadline(host_id, bot.clone(), bot_state.clone());
+                resend_prompts(game_snapshot.as_ref(), &bot, &bot_state).await
+            }
+        }
+        InGameCommand::Next => {
+            if snapshot.is_paused() {
+                return Err("Resume the game before ending the phase");
+            }
+            announce(String::from("The host has ended the phase early")).await;
+            force_phase_end(&game, snapshot.get_phase_number(), bot, bot_state).await;
+            Ok(())
+        }
+        _ => Err("Internal error: not a host command"),
+    }
+}
+
+/// Removes the player from their game, telling the remaining players and moving the game on if
+/// the player was holding it up
+async fn quit_game(
+    player_id: PlayerId,
+    bot: Bot,
+    bot_state: AsyncBotState,
+) -> Result<(), &'static str> {
+    let game = bot_state
+        .game_manager
+        .get_player_game(player_id)
+        .ok_or("Player is not in a game")?;
+    let (announcement, game, phase_opt) = game
+        .update(move |game| {
+            let player = game
+                .get_players()
+                .into_iter()
+                .find(|p| p.player_id == player_id)
+                .cloned()
+                .ok_or(GameError::NotAlive)?;
+            let announcement = if player.is_alive() {
+                format!("{} quit the game", game.get_setup().get_death_name(&player))
+            } else {
+                format!("{} left the game", player.username)
+            };
+
+            let phase_opt = game.quit(player_id)?.cloned();
+            let phase_opt = phase_opt.or_else(|| game.end_phase().cloned());
+            Ok::<_, GameError>((announcement, game.snapshot(), phase_opt))
+        })
+        .await?
+        .map_err(|_| "The game has already ended")?;
+
+    let poll_opt = bot_state
+        .poll_msg_ids
+        .lock()
+        .unwrap()
+        .remove(&player_id.into());
+    if let Some(message_id) = poll_opt {
+        if let Err(err) = bot.stop_poll(player_id, message_id).await {
+            log::error!("{err}");
+        }
+    }
+
+    // A game that has ended is removed through its remaining players, or through the player who
+    // quit if nobody remains
+    let host_id = match game.get_players().first() {
+        Some(player) => {
+            bot_state.game_manager.quit_game(player_id)?;
+            player.player_id
+        }
+        None => player_id,
+    };
+    for player in game.get_players() {
+        if let Err(err) = bot
+            .send_message(player.player_id, announcement.clone())
+            .await
+        {
+            log::error!("{err}");
+        }
+    }
+
+    match phase_opt {
+        Some(phase) => start_next_phase(Some(phase), host_id, bot, bot_state).await,
+        // The vote options have changed, so every open vote poll is replaced
+        None if matches!(game.get_phase(), GamePhase::Voting { .. }) => {
+            resend_prompts(game.as_ref(), &bot, &bot_state).await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Sends every idle player their prompt again, replacing the polls they have not answered yet
+async fn resend_prompts(
+    game: &dyn Game,
+    bot: &Bot,
+    bot_state: &AsyncBotState,
+) -> Result<(), &'static str> {
+    for player in game.get_idle_players() {
+        let poll_opt = bot_state
+            .poll_msg_ids
+            .lock()
+            .unwrap()
+            .remove(&player.player_id.into());
+        if let Some(message_id) = poll_opt {
+            if let Err(err) = bot.stop_poll(player.player_id, message_id).await {
+                log::error!("{err}");
+            }
+        }
+        send_prompt(game, player.player_id, bot, bot_state)
+            .await
+            .map_err(|_| "Failed to send prompt")?;
+    }
+    Ok(())
+}
+
+/// Hands the player's place in their game to the user, e.g. after the user sent the code from
+/// the player's /substitute. Tells everyone in the game and sends the user the current prompt
+pub async fn substitute_player(
+    player_id: PlayerId,
+    user: User,
+    bot: Bot,
+    bot_state: AsyncBotState,
+) -> Result<(), &'static str> {
+    let substitute_id = user.player_id;
+    if bot_state.game_manager.get_player_game(player_id).is_none() {
+        return Err("That player has already left their game");
+    }
+    // The substitute is registered before the game changes, so that a second substitute for the
+    // same player is turned away
+    let game = bot_state
+        .game_manager
+        .substitute_player(player_id, substitute_id)?;
+    let username = user.username.clone();
+    let res = game
+        .update(move |game| {
+            let former_username = game
+                .get_players()
+                .into_iter()
+                .find(|p| p.player_id == player_id)
+                .map(|p| p.username.clone())
+                .unwrap_or_default();
+            game.substitute_player(player_id, substitute_id, username)
+                .map(|()| (former_username, game.snapshot()))
+        })
+        .await
+        .and_then(|res| {
+            res.map_err(|err| match err {
+                GameError::NotAllowed => "You have already played in that game",
+                GameError::NotAlive => "That player is no longer alive",
+                _ => "That game has already ended",
+            })
+        });
+    let (former_username, game) = match res {
+        Ok(res) => res,
+        Err(message) => {
+            if let Err(err) = bot_state
+                .game_manager
+                .substitute_player(substitute_id, player_id)
+            {
+                log::error!("Failed to give {player_id} their place back: {err}");
+            }
+            return Err(message);
+        }
+    };
+
+    let poll_opt = bot_state
+        .poll_msg_ids
+        .lock()
+        .unwrap()
+        .remove(&player_id.into());
+    if let Some(message_id) = poll_opt {
+        if let Err(err) = bot.stop_poll(player_id, message_id).await {
+            log::error!("{err}");
+        }
+    }
+
+    let substitute = game
+        .get_players()
+        .into_iter()
+        .find(|p| p.player_id == substitute_id)
+        .ok_or("Internal error: substitute is not in the game")?;
+    let mut welcome = format!(
+        "You have taken over {former_username}'s place. You are a {}",
+        substitute.role
+    );
+    let investigations = game.get_investigations(substitute_id);
+    if !investigations.is_empty() {
+        welcome.push_str("\n\nInvestigation results so far:");
+        for investigation in investigations {
+            welcome.push_str(&format!("\n{investigation}"));
+        }
+    }
+
+    let mut messages = vec![(
+        player_id,
+        format!(
+            "{} has taken over your place. You have left the game",
+            user.username
+        ),
+    )];
+    for player in game.get_players() {
+        if player.player_id != substitute_id {
+            messages.push((
+                player.player_id,
+                format!("{former_username} has been replaced by {}", user.username),
+            ));
+        }
+    }
+    messages.push((substitute_id, welcome));
+    for (chat_id, text) in messages {
+        if let Err(err) = bot.send_message(chat_id, text).await {
+            log::error!("{err}");
+        }
+    }
+
+    send_prompt(game.as_ref(), substitute_id, &bot, &bot_state)
+        .await
+        .map_err(|_| "Failed to send the current prompt")
+}
+
+/// Sends the player the prompt of the game's current phase again, if the game is waiting on
+/// their input. Polls sent this way are closed like the ones sent at the start of the phase
+async fn send_prompt(
+    game: &dyn Game,
+    player_id: PlayerId,
+    bot: &Bot,
+    bot_state: &AsyncBotState,
+) -> Result<(), RequestError> {
+    let is_idle = game
+        .get_idle_players()
+        .iter()
+        .any(|p| p.player_id == player_id);
+    let poll = match game.get_phase() {
+        GamePhase::Night { .. } => {
+            if let Some((message, options)) = game.get_night_actions().remove(&player_id) {
+                if options.is_empty() {
+                    bot.send_message(player_id, message).await?;
+                } else {
+                    bot.send_message(player_id, message)
+                        .reply_markup(make_keyboard(options, game.get_phase_number()))
+                        .await?;
+                }
+            }
+            return Ok(());
+        }
+        GamePhase::Voting { .. } if is_idle => vote_poll(bot, player_id, game),
+        GamePhase::Trial { .. } if is_idle => verdict_poll(bot, player_id, game),
+        _ => return Ok(()),
+    };
+
+    let message = poll.await?;
+    remember_poll(bot_state, player_id, &message, game.get_phase_number());
+    Ok(())
+}
+
+/// Remembers the poll just sent to the player, so that it can be closed later and so that only
+/// its answers are taken. The player's earlier polls are forgotten
+fn remember_poll(
+    bot_state: &AsyncBotState,
+    player_id: PlayerId,
+    message: &Message,
+    phase_number: usize,
+) {
+    bot_state
+        .poll_msg_ids
+        .lock()
+        .unwrap()
+        .insert(player_id.into(), message.id);
+    if let Some(poll) = message.poll() {
+        let mut poll_phases = bot_state.poll_phases.lock().unwrap();
+        poll_phases.retain(|_, (id, _)| *id != player_id);
+        poll_phases.insert(poll.id.clone(), (player_id, phase_number));
+    }
+}
+
+/// Returns the phase number that the answered poll was sent to the player in, or `None` if it is
+/// not the player's current poll
+fn get_poll_phase(bot_state: &AsyncBotState, poll_answer: &PollAnswer) -> Option<usize> {
+    let player_id = PlayerId::from(poll_answer.user.id);
+    bot_state
+        .poll_phases
+        .lock()
+        .unwrap()
+        .get(&poll_answer.poll_id)
+        .filter(|(id, _)| *id == player_id)
+        .map(|(_, phase_number)| *phase_number)
+}
+
+/// Creates the poll that asks the player who to put on trial
+fn vote_poll(bot: &Bot, player_id: PlayerId, game: &dyn Game) -> <Bot as Requester>::SendPoll {
+    let votable_usernames = game
+        .get_vote_options()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| x.1)
+        .collect::<Vec<_>>();
+
+    bot.send_poll(player_id, "Who to put on trial?", votable_usernames)
+        .allows_multiple_answers(game.get_setup().allow_multiple_votes)
+        .is_anonymous(false)
+}
+
+/// Creates the poll that asks the player for their verdict on the defendant
+fn verdict_poll(bot: &Bot, player_id: PlayerId, game: &dyn Game) -> <Bot as Requester>::SendPoll {
+    let verdict_option_texts = game
+        .get_verdict_options()
+        .unwrap_or_default()
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>();
+
+    bot.send_poll(player_id, "Vote on trial: ", verdict_option_texts)
+        .is_anonymous(true)
+}
+
+/// Whether the player is in a game whose current phase passes `is_phase`
+async fn is_in_phase(
+    bot_state: &AsyncBotState,
+    player_id: PlayerId,
+    is_phase: fn(&GamePhase) -> bool,
+) -> bool {
+    let Some(game) = bot_state.game_manager.get_player_game(player_id) else {
+        return false;
+    };
+    game.run(move |game| is_phase(game.get_phase()))
+        .await
+        .unwrap_or(false)
+}
+
+/// Returns a copy of the player's game, so that its messages can be sent without holding up the
+/// game's task
+async fn get_snapshot(
+    bot_state: &AsyncBotState,
+    player_id: PlayerId,
+) -> Result<Box<dyn Game>, &'static str> {
+    bot_state
+        .game_manager
+        .get_player_game(player_id)
+        .ok_or("Player is not in a game")?
+        .run(|game| game.snapshot())
+        .await
+}
+
+/// Relays a player's text message to the players that are allowed to see it
+async fn handle_chat(bot_state: AsyncBotState, bot: Bot, msg: Message) -> Result<(), RequestError> {
+    let Some(text) = msg.text() else {
+        bot.send_message(
+            msg.chat.id,
+            "Only text messages can be sent to other players",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let sender_id = PlayerId::from(msg.chat.id);
+    let Some(game) = bot_state.game_manager.get_player_game(sender_id) else {
+        return Ok(());
+    };
+    let res = game
+        .run(move |game| {
+            let username = game
+                .get_players()
+                .iter()
+                .find(|p| p.player_id == sender_id)
+                .map(|p| p.username.clone())
+                .unwrap_or_default();
+
+            (username, game.route_chat_message(sender_id))
+        })
+        .await;
+    let Ok((username, route)) = res else {
+        return Ok(());
+    };
+
+    match route {
+        ChatRoute::Relay {
+            channel,
+            recipients,
+        } => {
+            let mut message_set = JoinSet::new();
+            let relayed_text = match channel {
+                ChatChannel::Day => format!("{username}: {text}"),
+                ChatChannel::Mafia => format!("[Mafia] {username}: {text}"),
+                ChatChannel::Graveyard => format!("[Graveyard] {username}: {text}"),
+            };
+            for player_id in recipients {
+                let bot_clone = bot.clone();
+                let text = relayed_text.clone();
+                message_set.spawn(async move { bot_clone.send_message(player_id, text).await });
+            }
+
+            while let Some(join_res) = message_set.join_next().await {
+                if let Ok(Err(err)) = join_res {
+                    log::error!("{err}");
+                }
+            }
+        }
+        ChatRoute::Reject { reason } => {
+            bot.send_message(msg.chat.id, reason).await?;
+        }
+    }
+
     Ok(())
 }
 
-/// `options` should be a vector of (text: String, data: String)
-fn make_keyboard(options: Vec<(ChatId, String)>) -> InlineKeyboardMarkup {
+/// `options` should be a vector of (text: String, data: String). The phase number is included in
+/// the callback data so that choices from an earlier phase's keyboard can be ignored
+fn make_keyboard(options: Vec<(PlayerId, String)>, phase_number: usize) -> InlineKeyboardMarkup {
     let keyboard = options
         .iter()
-        .map(|(chat_id, username)| {
+        .map(|(player_id, username)| {
             vec![InlineKeyboardButton::callback(
                 username,
-                chat_id.to_string(),
+                format!("{phase_number}:{player_id}"),
             )]
         })
         .collect::<Vec<_>>();
@@ -101,72 +702,291 @@ fn make_keyboard(options: Vec<(ChatId, String)>) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(keyboard)
 }
 
+/// Parses the callback data created by `make_keyboard` into `(phase_number, player_id)`. Returns
+/// `None` for other data, e.g. from keyboards sent before the phase number was included
+fn parse_keyboard_data(data: &str) -> Option<(usize, PlayerId)> {
+    let (phase_number, player_id) = data.split_once(':')?;
+    Some((
+        phase_number.parse::<usize>().ok()?,
+        PlayerId(player_id.parse::<i64>().ok()?),
+    ))
+}
+
+/// Restarts the current phase's deadline for every game in progress, e.g. after a restart, and
+/// sends the players the game is still waiting on their prompt again
+pub async fn resume_games(bot: Bot, bot_state: AsyncBotState) {
+    for game in bot_state.game_manager.get_games() {
+        let res = game
+            .run(|game| {
+                let host_id = game.get_players().first()?.player_id;
+                Some((host_id, game.snapshot()))
+            })
+            .await;
+        let Ok(Some((host_id, game))) = res else {
+            continue;
+        };
+
+        let res = if matches!(game.get_phase(), GamePhase::Ending) {
+            start_next_phase(
+                Some(GamePhase::Ending),
+                host_id,
+                bot.clone(),
+                bot_state.clone(),
+            )
+            .await
+        } else {
+            spawn_phase_deadline(host_id, bot.clone(), bot_state.clone());
+            resend_prompts(game.as_ref(), &bot, &bot_state).await
+        };
+        if let Err(err) = res {
+            log::error!("{err}");
+        }
+    }
+}
+
+/// Starts the first phase of the player's newly created game
+pub async fn start_game(
+    player_id: PlayerId,
+    bot: Bot,
+    bot_state: AsyncBotState,
+) -> Result<(), &'static str> {
+    let phase = bot_state
+        .game_manager
+        .get_player_game(player_id)
+        .ok_or("Player is not in a game")?
+        .run(|game| game.get_phase().clone())
+        .await?;
+
+    start_next_phase(Some(phase), player_id, bot, bot_state).await
+}
+
 async fn start_next_phase(
     phase_opt: Option<GamePhase>,
-    chat_id: ChatId,
+    player_id: PlayerId,
     bot: Bot,
     bot_state: AsyncBotState,
 ) -> Result<(), &'static str> {
     match phase_opt {
-        Some(GamePhase::Night { .. }) => start_night(chat_id, bot, bot_state).await,
-        Some(GamePhase::Trial { .. }) => start_trial(chat_id, bot, bot_state).await,
-        Some(GamePhase::Voting { .. }) => start_voting(chat_id, bot, bot_state).await,
-        Some(GamePhase::Ending) => start_ending(chat_id, bot, bot_state).await,
+        Some(GamePhase::Night { .. }) => start_night(player_id, bot, bot_state).await,
+        Some(GamePhase::Trial { .. }) => start_trial(player_id, bot, bot_state).await,
+        Some(GamePhase::Voting { .. }) => start_voting(player_id, bot, bot_state).await,
+        Some(GamePhase::Defense { .. } | GamePhase::LastWords { .. }) => {
+            start_speech(player_id, bot, bot_state).await
+        }
+        Some(GamePhase::Ending) => start_ending(player_id, bot, bot_state).await,
         None => Ok(()),
     }
 }
 
-pub async fn start_night(
-    chat_id: ChatId,
+/// Sends each player the private messages from the game's most recent transition
+async fn send_private_messages(game: &dyn Game, bot: Bot) -> Result<(), &'static str> {
+    let mut message_set = JoinSet::new();
+
+    for (player_id, messages) in game.get_private_messages() {
+        let bot_clone = bot.clone();
+        message_set.spawn(async move {
+            for text in messages {
+                if let Err(err) = bot_clone.send_message(player_id, text).await {
+                    return (player_id, Err(err));
+                }
+            }
+            (player_id, Ok(()))
+        });
+    }
+
+    // A player that cannot be reached does not stop the others from getting their messages
+    while let Some(join_res) = message_set.join_next().await {
+        match join_res {
+            Ok((player_id, Err(err))) => {
+                log::error!("Failed to send private message to {player_id}: {err}")
+            }
+            Ok((_, Ok(()))) => {}
+            Err(err) => log::error!("{err}"),
+        };
+    }
+
+    Ok(())
+}
+
+/// Returns the ids of the game's idle players, if the game is still in the same phase and has not
+/// been paused
+async fn get_idle_ids(game: &GameHandle, phase_number: usize) -> Option<Vec<PlayerId>> {
+    game.run(move |game| {
+        (game.get_phase_number() == phase_number && !game.is_paused()).then(|| {
+            game.get_idle_players()
+                .iter()
+                .map(|p| p.player_id)
+                .collect()
+        })
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Forces the game's current phase to end once its deadline passes. Players that have not given
+/// their input are warned shortly before the deadline. Paused games have no deadline
+fn spawn_phase_deadline(host_id: PlayerId, bot: Bot, bot_state: AsyncBotState) {
+    tokio::spawn(async move {
+        let Some(game) = bot_state.game_manager.get_player_game(host_id) else {
+            return;
+        };
+        let durations = bot_state.phase_durations;
+        let res = game
+            .run(move |game| {
+                let duration = match game.get_phase() {
+                    _ if game.is_paused() => return None,
+                    GamePhase::Night { .. } => durations.night,
+                    GamePhase::Voting { .. } => durations.voting,
+                    GamePhase::Defense { .. } => durations.defense,
+                    GamePhase::Trial { .. } => durations.trial,
+                    GamePhase::LastWords { .. } => durations.last_words,
+                    GamePhase::Ending => return None,
+                };
+                Some((game.get_phase_number(), duration))
+            })
+            .await;
+        let Ok(Some((phase_number, duration))) = res else {
+            return;
+        };
+        let warning = durations.warning.min(duration);
+        let deadline = Instant::now() + duration;
+        bot_state
+            .deadlines
+            .lock()
+            .unwrap()
+            .insert(game.get_game_id(), deadline);
+        // A later deadline replaces this one, e.g. once a paused game is resumed
+        let is_current =
+            || bot_state.deadlines.lock().unwrap().get(&game.get_game_id()) == Some(&deadline);
+
+        tokio::time::sleep_until(deadline - warning).await;
+
+        let Some(idle_ids) = get_idle_ids(&game, phase_number).await else {
+            return;
+        };
+        if !is_current() {
+            return;
+        }
+        let warning_text = format!("{} seconds left to make your choice!", warning.as_secs());
+        for player_id in idle_ids {
+            if let Err(err) = bot.send_message(player_id, warning_text.clone()).await {
+                log::error!("{err}");
+            }
+        }
+
+        tokio::time::sleep_until(deadline).await;
+
+        if is_current() {
+            force_phase_end(&game, phase_number, bot, bot_state.clone()).await;
+        }
+    });
+}
+
+/// Ends the game's phase with the inputs given so far, closing the polls that were never
+/// answered. Nothing happens if the phase has already ended or the game is paused
+async fn force_phase_end(
+    game: &GameHandle,
+    phase_number: usize,
+    bot: Bot,
+    bot_state: AsyncBotState,
+) {
+    // The phase is checked and ended in one update, so that a phase that has just ended
+    // through the players' inputs is left alone
+    let res = game
+        .update(move |game| {
+            if game.get_phase_number() != phase_number || game.is_paused() {
+                return None;
+            }
+            let idle_ids = game
+                .get_idle_players()
+                .iter()
+                .map(|p| p.player_id)
+                .collect::<Vec<_>>();
+            // The host may have quit during the phase
+            let host_id = game.get_players().first()?.player_id;
+            Some((idle_ids, host_id, game.force_end_phase().cloned()))
+        })
+        .await;
+    let Ok(Some((idle_ids, host_id, phase_opt))) = res else {
+        return;
+    };
+    let unanswered_polls = {
+        let mut poll_msg_ids = bot_state.poll_msg_ids.lock().unwrap();
+        idle_ids
+            .into_iter()
+            .filter_map(|player_id| {
+                let chat_id = ChatId::from(player_id);
+                let message_id = poll_msg_ids.remove(&chat_id)?;
+                Some((chat_id, message_id))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Close the polls that were never answered
+    for (chat_id, message_id) in unanswered_polls {
+        if let Err(err) = bot.stop_poll(chat_id, message_id).await {
+            log::error!("{err}");
+        }
+    }
+
+    if let Err(err) = start_next_phase(phase_opt, host_id, bot, bot_state).await {
+        log::error!("{err}");
+    }
+}
+
+async fn start_night(
+    player_id: PlayerId,
     bot: Bot,
     bot_state: AsyncBotState,
 ) -> Result<(), &'static str> {
-    let game = {
-        let mut state_lock = bot_state.lock().unwrap();
-        let game = state_lock.game_manager.get_player_game(chat_id).unwrap();
-        game.snapshot()
-    };
+    let game = get_snapshot(&bot_state, player_id).await?;
+    // The deadline is set before any message is sent, so that the night still ends if some
+    // players cannot be reached
+    spawn_phase_deadline(player_id, bot.clone(), bot_state);
     let mut message_set = JoinSet::new();
 
     // Queue transition messages
     for player in game.get_players() {
         let bot_clone = bot.clone();
-        let chat_id = player.chat_id;
+        let player_id = player.player_id;
         let text = game.get_transition_message();
-        message_set.spawn(async move { bot_clone.send_message(chat_id, text).await });
+        message_set
+            .spawn(async move { (player_id, bot_clone.send_message(player_id, text).await) });
     }
 
     // Queue targetting messages
     let night_actions = game.get_night_actions();
-    for (chat_id, (message, options)) in night_actions {
+    for (player_id, (message, options)) in night_actions {
         let bot_clone = bot.clone();
-        if options.len() > 0 {
-            let keyboard = make_keyboard(options.to_vec());
+        if !options.is_empty() {
+            let keyboard = make_keyboard(options.to_vec(), game.get_phase_number());
             message_set.spawn(async move {
-                bot_clone
-                    .send_message(chat_id, message)
+                let res = bot_clone
+                    .send_message(player_id, message)
                     .reply_markup(keyboard)
-                    .await
+                    .await;
+                (player_id, res)
             });
         } else {
-            message_set.spawn(async move { bot_clone.send_message(chat_id, message).await });
+            message_set.spawn(async move {
+                (player_id, bot_clone.send_message(player_id, message).await)
+            });
         }
     }
 
+    // A player that cannot be reached does not stop the others from getting their prompts
     while let Some(join_res) = message_set.join_next().await {
         match join_res {
-            Ok(tele_res) => {
-                if let Err(_) = tele_res {
-                    return Err("Failed to send targetting message");
-                }
-            }
-            Err(_) => {
-                return Err("Internal Error: join error");
+            Ok((player_id, Err(err))) => {
+                log::error!("Failed to send night message to {player_id}: {err}")
             }
-        }
+            Ok((_, Ok(_))) => {}
+            Err(err) => log::error!("{err}"),
+        };
     }
 
-    Ok(())
+    send_private_messages(game.as_ref(), bot).await
 }
 
 async fn handle_night(
@@ -175,108 +995,141 @@ async fn handle_night(
     q: CallbackQuery,
 ) -> Result<(), RequestError> {
     // Add night_action to game
-    let source_id = ChatId::from(q.from.id);
-    let target_id = ChatId(q.data.as_ref().unwrap().parse::<i64>().unwrap());
-
-    let (game, phase_opt) = {
-        // Wrap code in braces to release lock on bot_state
-        let mut state_lock = bot_state.lock().unwrap();
-
-        let game = state_lock
-            .game_manager
-            .get_player_game(q.from.id.into())
-            .unwrap();
-        game.add_night_action(source_id, target_id);
+    let source_id = PlayerId::from(q.from.id);
+    let Some((phase_number, target_id)) =
+        parse_keyboard_data(q.data.as_deref().unwrap_or_default())
+    else {
+        bot.answer_callback_query(q.id)
+            .text(GameError::WrongPhase.to_string())
+            .await?;
+        return Ok(());
+    };
 
-        (game.snapshot(), game.end_phase().cloned())
+    let Some(game) = bot_state.game_manager.get_player_game(source_id) else {
+        return Ok(());
+    };
+    let res = game
+        .update(move |game| {
+            if game.get_phase_number() != phase_number {
+                return Err(GameError::WrongPhase);
+            }
+            game.add_night_action(source_id, target_id).map(|()| {
+                let is_mafia = game
+                    .get_players()
+                    .iter()
+                    .any(|p| p.player_id == source_id && p.role == Role::Mafia);
+                let team_picks_opt = is_mafia.then(|| game.get_team_picks().ok()).flatten();
+                (game.snapshot(), team_picks_opt, game.end_phase().cloned())
+            })
+        })
+        .await
+        // The choices of a game that has ended have expired
+        .unwrap_or(Err(GameError::WrongPhase));
+    let (game, team_picks_opt, phase_opt) = match res {
+        Ok(res) => res,
+        Err(err) => {
+            bot.answer_callback_query(q.id)
+                .text(err.to_string())
+                .await?;
+            return Ok(());
+        }
     };
 
-    // Answer callback query
-    bot.answer_callback_query(q.id).await?;
+    // Answer callback query, keeping the keyboard so that the choice can be changed. The phase
+    // may have ended already, so a failure here must not stop the next phase from starting
+    if let Err(err) = bot.answer_callback_query(q.id).await {
+        log::error!("{err}");
+    }
     if let Some(Message { id, chat, .. }) = q.message {
-        let chosen_text = game
-            .get_night_actions()
-            .get(&source_id)
-            .unwrap()
-            .1
+        let (prompt, options) = game.get_night_actions().remove(&source_id).unwrap();
+        let chosen_text = options
             .iter()
-            .find(|(chat_id, _username)| *chat_id == target_id)
+            .find(|(player_id, _username)| *player_id == target_id)
             .unwrap()
             .1
             .clone();
 
-        bot.edit_message_text(chat.id, id, format!("You chose: {chosen_text}"))
-            .await?;
+        let edit_res = bot
+            .edit_message_text(chat.id, id, format!("{prompt}\n\nYou chose: {chosen_text}"))
+            .reply_markup(make_keyboard(options, phase_number))
+            .await;
+        if let Err(err) = edit_res {
+            log::error!("{err}");
+        }
     }
 
-    start_next_phase(phase_opt, source_id, bot, bot_state).await;
+    // Show the Mafia's picks to the rest of the Mafia
+    if let Some((mafia_ids, summary)) = team_picks_opt {
+        for player_id in mafia_ids.into_iter().filter(|id| *id != source_id) {
+            if let Err(err) = bot.send_message(player_id, summary.clone()).await {
+                log::error!("{err}");
+            }
+        }
+    }
+
+    if let Err(err) = start_next_phase(phase_opt, source_id, bot, bot_state).await {
+        log::error!("{err}");
+    }
 
     Ok(())
 }
 
 async fn start_voting(
-    host_id: ChatId,
+    host_id: PlayerId,
     bot: Bot,
     bot_state: AsyncBotState,
 ) -> Result<(), &'static str> {
-    let game = {
-        let mut state_lock = bot_state.lock().unwrap();
-        let game = state_lock.game_manager.get_player_game(host_id).unwrap();
-        game.snapshot()
-    };
-
-    let mut message_set = JoinSet::new();
-
-    let votable_usernames = game
-        .get_vote_options()
+    let game = get_snapshot(&bot_state, host_id).await?;
+    game.get_vote_options()
+        .map_err(|_| "Internal error: voting started outside of GamePhase::Voting")?;
+    let voter_ids = game
+        .get_voters()
+        .unwrap_or_default()
         .iter()
-        .map(|x| x.1.clone())
-        .collect::<Vec<_>>();
+        .map(|p| p.player_id)
+        .collect();
+
+    send_polls(game.as_ref(), host_id, voter_ids, vote_poll, bot, bot_state).await
+}
 
-    for player in game.get_voters() {
-        let temp = bot.clone();
-        let chat_id = player.chat_id;
-        let transition_message = game.get_transition_message().clone();
-        let option_text = votable_usernames.clone();
+/// Starts a phase that is decided by polls, sending each recipient the transition message and
+/// the poll created by `poll`. The phase's deadline is set before any message is sent
+async fn send_polls(
+    game: &dyn Game,
+    host_id: PlayerId,
+    recipient_ids: Vec<PlayerId>,
+    poll: fn(&Bot, PlayerId, &dyn Game) -> <Bot as Requester>::SendPoll,
+    bot: Bot,
+    bot_state: AsyncBotState,
+) -> Result<(), &'static str> {
+    spawn_phase_deadline(host_id, bot.clone(), bot_state.clone());
 
+    let mut message_set = JoinSet::new();
+    for player_id in recipient_ids {
+        let bot_clone = bot.clone();
+        let transition_message = game.get_transition_message();
+        let poll = poll(&bot, player_id, game);
         message_set.spawn(async move {
-            temp.send_message(chat_id, transition_message).await;
+            if let Err(err) = bot_clone.send_message(player_id, transition_message).await {
+                return (player_id, Err(err));
+            }
 
-            let poll_res = temp
-                .send_poll(chat_id, format!("Who to put on trial?"), option_text)
-                .allows_multiple_answers(true)
-                .is_anonymous(false)
-                .await;
-            (chat_id, poll_res)
+            (player_id, poll.await)
         });
     }
 
-    let mut poll_id_map = HashMap::new();
+    // A player that cannot be reached, e.g. after blocking the bot, is left out
     while let Some(join_res) = message_set.join_next().await {
         match join_res {
-            Ok((chat_id, tele_res)) => match tele_res {
-                Ok(message) => {
-                    poll_id_map.insert(chat_id, message.id);
-                }
-                Err(err) => {
-                    panic!("{err}");
-                }
-            },
-            Err(err) => {
-                panic!("{err}");
+            Ok((player_id, Ok(message))) => {
+                remember_poll(&bot_state, player_id, &message, game.get_phase_number())
             }
+            Ok((player_id, Err(err))) => log::error!("Failed to send poll to {player_id}: {err}"),
+            Err(err) => log::error!("{err}"),
         };
     }
 
-    bot_state
-        .lock()
-        .unwrap()
-        .game_manager
-        .get_player_game(host_id)
-        .unwrap()
-        .add_poll_msg_ids(poll_id_map);
-
-    Ok(())
+    send_private_messages(game, bot).await
 }
 
 /*
@@ -293,85 +1146,107 @@ async fn handle_vote(
     bot: Bot,
     poll_answer: PollAnswer,
 ) -> Result<(), teloxide::RequestError> {
-    let chat_id = ChatId::from(poll_answer.user.id);
+    let player_id = PlayerId::from(poll_answer.user.id);
 
     // Add votes to game
-    let (message_id, phase_opt) = {
-        let state_lock = &mut bot_state.lock().unwrap();
-        let game = state_lock.game_manager.get_player_game(chat_id).unwrap();
-        (
-            *game.get_poll_msg_ids().get(&chat_id).unwrap(),
-            game.end_phase().cloned(),
-        )
+    let Some(game) = bot_state.game_manager.get_player_game(player_id) else {
+        return Ok(());
+    };
+    let option_ids = poll_answer.option_ids.clone();
+    let poll_phase_opt = get_poll_phase(&bot_state, &poll_answer);
+    let res = game
+        .update(move |game| {
+            // Answers to polls from an earlier phase have expired
+            if poll_phase_opt != Some(game.get_phase_number()) {
+                return Err(GameError::WrongPhase);
+            }
+            game.add_vote(player_id, option_ids)
+                .map(|()| game.end_phase().cloned())
+        })
+        .await
+        .unwrap_or(Err(GameError::WrongPhase));
+    let phase_opt = match res {
+        Ok(phase_opt) => phase_opt,
+        Err(err) => {
+            bot.send_message(player_id, err.to_string()).await?;
+            return Ok(());
+        }
     };
+    let message_id_opt = bot_state
+        .poll_msg_ids
+        .lock()
+        .unwrap()
+        .remove(&player_id.into());
 
-    bot.stop_poll(poll_answer.user.id, message_id).await?;
+    if let Some(message_id) = message_id_opt {
+        if let Err(err) = bot.stop_poll(poll_answer.user.id, message_id).await {
+            log::error!("{err}");
+        }
+    }
 
-    start_next_phase(phase_opt, chat_id, bot, bot_state).await;
+    if let Err(err) = start_next_phase(phase_opt, player_id, bot, bot_state).await {
+        log::error!("{err}");
+    }
 
     Ok(())
 }
 
 async fn start_trial(
-    host_id: ChatId,
+    host_id: PlayerId,
     bot: Bot,
     bot_state: AsyncBotState,
 ) -> Result<(), &'static str> {
-    let game = {
-        let mut state_lock = bot_state.lock().unwrap();
-        let game = state_lock.game_manager.get_player_game(host_id).unwrap();
-        game.snapshot()
-    };
-
-    let verdict_option_texts = game
-        .get_verdict_options()
+    let game = get_snapshot(&bot_state, host_id).await?;
+    game.get_verdict_options()
+        .map_err(|_| "Internal error: trial started outside of GamePhase::Trial")?;
+    let juror_ids = game
+        .get_jury()
+        .unwrap_or_default()
         .iter()
-        .map(|v| v.to_string())
-        .collect::<Vec<_>>();
+        .map(|p| p.player_id)
+        .collect();
+
+    send_polls(
+        game.as_ref(),
+        host_id,
+        juror_ids,
+        verdict_poll,
+        bot,
+        bot_state,
+    )
+    .await
+}
+
+/// Starts a phase in which a single player speaks to everyone, e.g. the defendant's defense. The
+/// phase lasts until its deadline, as nobody has a choice to make
+async fn start_speech(
+    host_id: PlayerId,
+    bot: Bot,
+    bot_state: AsyncBotState,
+) -> Result<(), &'static str> {
+    let game = get_snapshot(&bot_state, host_id).await?;
+    spawn_phase_deadline(host_id, bot.clone(), bot_state);
 
     let mut message_set = JoinSet::new();
-    for player in game.get_voters() {
+    for player in game.get_players() {
         let bot_clone = bot.clone();
-        let chat_id = player.chat_id;
-        let transition_message = game.get_transition_message().clone();
-        let options_text = verdict_option_texts.clone();
-        message_set.spawn(async move {
-            bot_clone.send_message(chat_id, transition_message).await;
-
-            let poll_res = bot_clone
-                .send_poll(chat_id, format!("Vote on trial: "), options_text)
-                .is_anonymous(true)
-                .await;
-            (chat_id, poll_res)
-        });
+        let player_id = player.player_id;
+        let text = game.get_transition_message();
+        message_set
+            .spawn(async move { (player_id, bot_clone.send_message(player_id, text).await) });
     }
 
-    let mut poll_id_map = HashMap::new();
     while let Some(join_res) = message_set.join_next().await {
         match join_res {
-            Ok((chat_id, tele_res)) => match tele_res {
-                Ok(message) => {
-                    poll_id_map.insert(chat_id, message.id);
-                }
-                Err(err) => {
-                    panic!("{err}");
-                }
-            },
-            Err(err) => {
-                panic!("{err}");
+            Ok((player_id, Err(err))) => {
+                log::error!("Failed to send transition message to {player_id}: {err}")
             }
+            Ok((_, Ok(_))) => {}
+            Err(err) => log::error!("{err}"),
         };
     }
 
-    bot_state
-        .lock()
-        .unwrap()
-        .game_manager
-        .get_player_game(host_id)
-        .unwrap()
-        .add_poll_msg_ids(poll_id_map);
-
-    Ok(())
+    send_private_messages(game.as_ref(), bot).await
 }
 
 async fn handle_trial(
@@ -379,67 +1254,120 @@ async fn handle_trial(
     bot: Bot,
     poll_answer: PollAnswer,
 ) -> Result<(), teloxide::RequestError> {
-    let chat_id = ChatId::from(poll_answer.user.id);
-    assert_eq!(
-        poll_answer.option_ids.len(),
-        1,
-        "Internal error: trial poll options_ids.len != 1"
-    );
-    let chosen_id = poll_answer.option_ids.first().unwrap();
+    let player_id = PlayerId::from(poll_answer.user.id);
+    // A trial poll has exactly one answer, unless the vote was retracted
+    let chosen_id = poll_answer.option_ids.first().copied().unwrap_or(-1);
 
     // Add verdict to game
-    let (message_id, phase_opt) = {
-        let state_lock = &mut bot_state.lock().unwrap();
-        let game = state_lock.game_manager.get_player_game(chat_id).unwrap();
-        game.add_verdict(chat_id, *chosen_id);
-        (
-            *game.get_poll_msg_ids().get(&chat_id).unwrap(),
-            game.end_phase().cloned(),
-        )
+    let Some(game) = bot_state.game_manager.get_player_game(player_id) else {
+        return Ok(());
     };
+    let poll_phase_opt = get_poll_phase(&bot_state, &poll_answer);
+    let res = game
+        .update(move |game| {
+            if poll_phase_opt != Some(game.get_phase_number()) {
+                return Err(GameError::WrongPhase);
+            }
+            game.add_verdict(player_id, chosen_id)
+                .map(|()| game.end_phase().cloned())
+        })
+        .await
+        .unwrap_or(Err(GameError::WrongPhase));
+    let phase_opt = match res {
+        Ok(phase_opt) => phase_opt,
+        Err(err) => {
+            bot.send_message(player_id, err.to_string()).await?;
+            return Ok(());
+        }
+    };
+    let message_id_opt = bot_state
+        .poll_msg_ids
+        .lock()
+        .unwrap()
+        .remove(&player_id.into());
 
     // Stop poll
-    bot.stop_poll(poll_answer.user.id, message_id).await?;
+    if let Some(message_id) = message_id_opt {
+        if let Err(err) = bot.stop_poll(poll_answer.user.id, message_id).await {
+            log::error!("{err}");
+        }
+    }
 
-    start_next_phase(phase_opt, chat_id, bot, bot_state).await;
+    if let Err(err) = start_next_phase(phase_opt, player_id, bot, bot_state).await {
+        log::error!("{err}");
+    }
 
     Ok(())
 }
 
+/// Splits a long message into paragraphs that each fit within Telegram's message length limit
+fn split_message(text: &str) -> Vec<String> {
+    const MAX_MESSAGE_LEN: usize = 4096;
+
+    let mut messages: Vec<String> = Vec::new();
+    for paragraph in text.split("\n\n") {
+        match messages.last_mut() {
+            Some(message)
+                if message.chars().count() + paragraph.chars().count() + 2 <= MAX_MESSAGE_LEN =>
+            {
+                message.push_str("\n\n");
+                message.push_str(paragraph);
+            }
+            _ => messages.push(paragraph.to_string()),
+        }
+    }
+    messages
+}
+
 async fn start_ending(
-    host_id: ChatId,
+    host_id: PlayerId,
     bot: Bot,
     bot_state: AsyncBotState,
 ) -> Result<(), &'static str> {
-    let game_snapshot = {
-        let mut state_lock = bot_state.lock().unwrap();
-        state_lock.game_manager.remove_game(host_id)
-    };
-    let game = game_snapshot.unwrap();
+    // Whoever removes the game first announces its ending
+    let game = bot_state
+        .game_manager
+        .remove_game(host_id)
+        .ok_or("Game has already ended")?;
+    bot_state
+        .deadlines
+        .lock()
+        .unwrap()
+        .remove(&game.get_game_id());
+    let game = game.stop().await?;
 
     let mut message_set = JoinSet::new();
 
-    let ending_message = game.get_transition_message();
+    let mut messages = vec![game.get_transition_message()];
+    messages.extend(split_message(&format!(
+        "Game summary\n\n{}",
+        game.get_summary()
+    )));
     for player in game.get_players() {
         let bot_clone = bot.clone();
-        let chat_id = player.chat_id;
-        let text = ending_message.clone();
+        let player_id = player.player_id;
+        let messages = messages.clone();
 
-        message_set.spawn(async move { bot_clone.send_message(chat_id, text).await });
+        // The summary is sent in order, right after the ending message
+        message_set.spawn(async move {
+            for text in messages {
+                if let Err(err) = bot_clone.send_message(player_id, text).await {
+                    return (player_id, Err(err));
+                }
+            }
+            (player_id, Ok(()))
+        });
     }
 
     while let Some(join_res) = message_set.join_next().await {
         match join_res {
-            Ok(tele_res) => {
-                if let Err(_) = tele_res {
-                    return Err("Failed to send targetting message");
-                }
-            }
-            Err(_) => {
-                return Err("Internal Error: join error");
+            Ok((player_id, Err(err))) => {
+                log::error!("Failed to send game summary to {player_id}: {err}")
             }
-        }
+            Ok((_, Ok(()))) => {}
+            Err(err) => log::error!("{err}"),
+        };
     }
 
-    Ok(())
+    send_private_messages(game.as_ref(), bot).await
 }
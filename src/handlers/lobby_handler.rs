@@ -1,7 +1,8 @@
+use rand::Rng;
 use teloxide::{prelude::*, utils::command::BotCommands};
 
-use super::{game_handler::start_night, AsyncBotState};
-use crate::game::{game_v1::GameV1, Game};
+use super::{game_handler::start_game, AsyncBotState};
+use crate::game::{game_setup::GameSetup, game_v1::GameV1, Game, PlayerId};
 use crate::{game_manager::GameManager, lobby_manager::LobbyManager};
 
 pub fn get_lobby_handler() -> Handler<
@@ -13,10 +14,10 @@ pub fn get_lobby_handler() -> Handler<
     Update::filter_message()
         .filter(|msg: Message, bot_state: AsyncBotState| {
             bot_state
+                .lobby_manager
                 .lock()
                 .unwrap()
-                .lobby_manager
-                .get_chats_lobby(msg.chat.id)
+                .get_players_lobby(msg.chat.id.into())
                 .is_some()
         })
         .filter_command::<LobbyCommand>()
@@ -32,6 +33,15 @@ enum LobbyCommand {
     Players,
     #[command(description = "Quit lobby")]
     Quit,
+    #[command(description = "Show the game setup")]
+    Setup,
+    #[command(
+        description = "Change the game setup (host only). See /setup for the options",
+        parse_with = "split"
+    )]
+    Set { option: String, value: String },
+    #[command(description = "Remove the player with the number shown by /players (host only)")]
+    Kick { number: String },
     #[command(description = "Start game")]
     Start,
 }
@@ -43,68 +53,158 @@ async fn lobby_handler(
     cmd: LobbyCommand,
 ) -> Result<(), teloxide::RequestError> {
     let mut game_opt: Option<Box<dyn Game>> = None;
+    let mut kicked_opt: Option<PlayerId> = None;
     let text = match cmd {
         LobbyCommand::Help => LobbyCommand::descriptions().to_string(),
         LobbyCommand::Players => {
-            let state_lock = bot_state.lock().unwrap();
-            match state_lock.lobby_manager.get_chats_lobby(msg.chat.id) {
+            let lobby_manager = bot_state.lobby_manager.lock().unwrap();
+            match lobby_manager.get_players_lobby(msg.chat.id.into()) {
                 Some(lobby) => {
                     let host_id = lobby.host_id;
                     let mut player_index = 0;
-                    lobby
+                    let players = lobby
                         .users
                         .iter()
                         .map(|p| -> String {
                             player_index += 1;
-                            if p.chat_id == host_id {
+                            if p.player_id == host_id {
                                 format!("{}. {} (host)", player_index, p.username)
                             } else {
                                 format!("{}. {}", player_index, p.username)
                             }
                         })
                         .collect::<Vec<_>>()
-                        .join("\n")
+                        .join("\n");
+
+                    format!(
+                        "{players}\n\n{}",
+                        lobby.setup.describe_roles(lobby.users.len())
+                    )
                 }
-                None => format!("Internal error: player should be in a lobby but is not!"),
+                None => String::from("Internal error: player should be in a lobby but is not!"),
             }
         }
         LobbyCommand::Quit => {
-            let mut state_lock = bot_state.lock().unwrap();
-            state_lock
-                .lobby_manager
-                .get_chats_lobby(msg.chat.id)
-                .unwrap();
-            match state_lock.lobby_manager.quit_lobby(msg.chat.id) {
+            let mut lobby_manager = bot_state.lobby_manager.lock().unwrap();
+            lobby_manager.get_players_lobby(msg.chat.id.into()).unwrap();
+            match lobby_manager.quit_lobby(msg.chat.id.into()) {
                 Ok(lobby_id) => format!("Quit lobby: {}", lobby_id),
                 Err(message) => format!("Encountered error: {}", message),
             }
         }
-        LobbyCommand::Start => {
-            let mut state_lock = bot_state.lock().unwrap();
-            let lobby_manager = &mut state_lock.lobby_manager;
+        LobbyCommand::Setup => {
+            let lobby_manager = bot_state.lobby_manager.lock().unwrap();
+            match lobby_manager.get_players_lobby(msg.chat.id.into()) {
+                Some(lobby) => format!(
+                    "{}\n\nThe host can change the setup with:\n{}",
+                    lobby.setup.describe(lobby.users.len()),
+                    GameSetup::describe_options()
+                ),
+                None => String::from("Internal error: player should be in a lobby but is not!"),
+            }
+        }
+        LobbyCommand::Set { option, value } => {
+            let mut lobby_manager = bot_state.lobby_manager.lock().unwrap();
 
-            if let Some(lobby) = lobby_manager.get_chats_lobby(msg.chat.id) {
-                if lobby.users.len() >= 3 {
-                    let game = GameV1::from_lobby(lobby);
-                    if let Err(err) = lobby_manager.close_lobby(lobby.lobby_id) {
-                        panic!("{err}");
-                    };
-                    game_opt = Some(game.snapshot());
-                    state_lock.game_manager.add_game(Box::new(game));
+            match lobby_manager.get_players_lobby(msg.chat.id.into()) {
+                Some(lobby) if lobby.host_id != PlayerId::from(msg.chat.id) => {
+                    String::from("Only the host can change the game setup")
+                }
+                Some(lobby) => {
+                    let lobby_id = lobby.lobby_id;
+                    let player_count = lobby.users.len();
+                    let mut setup = lobby.setup.clone();
+                    match setup.set_option(&option, &value, player_count) {
+                        Ok(()) => match lobby_manager.set_lobby_setup(lobby_id, setup) {
+                            Ok(lobby) => format!(
+                                "Updated game setup:\n{}",
+                                lobby.setup.describe(player_count)
+                            ),
+                            Err(message) => format!("Encountered error: {}", message),
+                        },
+                        Err(message) => format!("Cannot change setup: {}", message),
+                    }
+                }
+                None => String::from("Internal error: player should be in a lobby but is not!"),
+            }
+        }
+        LobbyCommand::Kick { number } => {
+            let mut lobby_manager = bot_state.lobby_manager.lock().unwrap();
+            let host_id = PlayerId::from(msg.chat.id);
 
-                    format!("Started lobby")
+            match lobby_manager.get_players_lobby(host_id) {
+                Some(lobby) if lobby.host_id != host_id => {
+                    String::from("Only the host can remove players")
+                }
+                Some(lobby) => {
+                    let user_opt = number
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| lobby.users.get(n.checked_sub(1)?));
+                    match user_opt {
+                        None => {
+                            format!("There is no player {number}. See /players for the numbers")
+                        }
+                        Some(user) if user.player_id == host_id => {
+                            String::from("Use /quit to leave the lobby")
+                        }
+                        Some(user) => {
+                            let (player_id, username) = (user.player_id, user.username.clone());
+                            match lobby_manager.quit_lobby(player_id) {
+                                Ok(_) => {
+                                    kicked_opt = Some(player_id);
+                                    format!("Removed {username} from the lobby")
+                                }
+                                Err(message) => format!("Encountered error: {}", message),
+                            }
+                        }
+                    }
+                }
+                None => String::from("Internal error: player should be in a lobby but is not!"),
+            }
+        }
+        LobbyCommand::Start => {
+            let seed = bot_state.rng.lock().unwrap().gen();
+            let mut lobby_manager = bot_state.lobby_manager.lock().unwrap();
+
+            if let Some(lobby) = lobby_manager.get_players_lobby(msg.chat.id.into()) {
+                if lobby.users.len() < 3 {
+                    String::from("Cannot start game: Need 3 or more players")
+                } else if let Err(message) = lobby.setup.validate(lobby.users.len()) {
+                    format!("Cannot start game: {}", message)
                 } else {
-                    format!("Cannot start game: Need 3 or more players")
+                    log::info!("Starting lobby {} with seed {}", lobby.lobby_id, seed);
+                    let game = GameV1::from_lobby(lobby, seed);
+                    let lobby_id = lobby.lobby_id;
+                    match lobby_manager.close_lobby(lobby_id) {
+                        Ok(()) => {
+                            game_opt = Some(game.snapshot());
+                            bot_state.game_manager.add_game(Box::new(game));
+
+                            String::from("Started lobby")
+                        }
+                        Err(err) => {
+                            log::error!("Failed to close lobby {lobby_id}: {err}");
+                            format!("Cannot start game: {}", err)
+                        }
+                    }
                 }
             } else {
-                format!("Internal error: failed to find lobby to start")
+                String::from("Internal error: failed to find lobby to start")
             }
         }
     };
 
     bot.send_message(msg.chat.id, text).await?;
-    if let Some(_) = game_opt {
-        start_night(msg.chat.id, bot, bot_state).await;
+    if let Some(player_id) = kicked_opt {
+        bot.send_message(player_id, "The host removed you from the lobby")
+            .await?;
+    }
+    if game_opt.is_some() {
+        if let Err(err) = start_game(msg.chat.id.into(), bot, bot_state).await {
+            log::error!("{err}");
+        }
     }
 
     Ok(())
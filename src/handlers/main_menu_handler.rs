@@ -1,6 +1,6 @@
 use teloxide::{prelude::*, utils::command::BotCommands};
 
-use super::AsyncBotState;
+use super::{game_handler::substitute_player, AsyncBotState};
 use crate::lobby_manager::*;
 
 pub fn get_main_menu_handler() -> Handler<
@@ -12,10 +12,10 @@ pub fn get_main_menu_handler() -> Handler<
     Update::filter_message()
         .filter(|msg: Message, bot_state: AsyncBotState| {
             bot_state
+                .lobby_manager
                 .lock()
                 .unwrap()
-                .lobby_manager
-                .get_chats_lobby(msg.chat.id)
+                .get_players_lobby(msg.chat.id.into())
                 .is_none()
         })
         .filter_command::<MainMenuCommand>()
@@ -29,7 +29,9 @@ enum MainMenuCommand {
     Help,
     #[command(description = "Host a lobby")]
     Host,
-    #[command(description = "Join a lobby")]
+    #[command(
+        description = "Join a lobby, or take over a player's place with their substitute code"
+    )]
     Join { code: i32 },
 }
 
@@ -42,10 +44,10 @@ async fn main_menu_handler(
     let text = match cmd {
         MainMenuCommand::Help => MainMenuCommand::descriptions().to_string(),
         MainMenuCommand::Host => {
-            let mut state_lock = bot_state.lock().unwrap();
+            let mut lobby_manager = bot_state.lobby_manager.lock().unwrap();
 
-            match state_lock.lobby_manager.create_lobby(User {
-                chat_id: msg.chat.id,
+            match lobby_manager.create_lobby(User {
+                player_id: msg.chat.id.into(),
                 username: String::from(msg.chat.username().unwrap_or("(no name)")),
             }) {
                 Ok(lobby) => {
@@ -55,19 +57,40 @@ async fn main_menu_handler(
             }
         }
         MainMenuCommand::Join { code } => {
-            let mut state_lock = bot_state.lock().unwrap();
+            let user = User {
+                player_id: msg.chat.id.into(),
+                username: String::from(msg.chat.username().unwrap_or("(no name)")),
+            };
+            let player_id_opt = bot_state
+                .substitute_codes
+                .lock()
+                .unwrap()
+                .get(&code)
+                .copied();
 
-            match state_lock.lobby_manager.join_lobby(
-                LobbyId(code),
-                User {
-                    chat_id: msg.chat.id,
-                    username: String::from(msg.chat.username().unwrap_or("(no name)")),
-                },
-            ) {
-                Ok(_) => {
-                    format!("Joined lobby {}", code)
+            match player_id_opt {
+                Some(player_id) => {
+                    match substitute_player(player_id, user, bot.clone(), bot_state.clone()).await {
+                        // The code is only used up once it has worked. The substitute has already
+                        // been sent their place in the game
+                        Ok(()) => {
+                            bot_state.substitute_codes.lock().unwrap().remove(&code);
+                            return Ok(());
+                        }
+                        Err(message) => format!("Encountered error: {}", message),
+                    }
                 }
-                Err(message) => format!("Encountered error: {}", message),
+                None => match bot_state
+                    .lobby_manager
+                    .lock()
+                    .unwrap()
+                    .join_lobby(LobbyId(code), user)
+                {
+                    Ok(_) => {
+                        format!("Joined lobby {}", code)
+                    }
+                    Err(message) => format!("Encountered error: {}", message),
+                },
             }
         }
     };
@@ -1,34 +1,52 @@
-use std::error::Error;
+use std::{env, error::Error};
 use teloxide::prelude::*;
 
 use crate::handlers::{
-    game_handler::get_game_handler, lobby_handler::get_lobby_handler,
-    main_menu_handler::get_main_menu_handler, new_async_bot_state,
+    game_handler::{get_game_handler, resume_games},
+    lobby_handler::get_lobby_handler,
+    main_menu_handler::get_main_menu_handler,
+    new_async_bot_state,
 };
+pub use crate::handlers::{open_async_bot_state, AsyncBotState, PhaseDurations};
 
-mod game;
+// The rules engine and its storage, which know nothing about Telegram
+pub mod game;
 mod game_manager;
+pub mod lobby_manager;
+pub mod simulator;
+
+// The Telegram frontend
 mod handlers;
-mod lobby_manager;
 
+/// Starts the bot with the token in `TELOXIDE_TOKEN`. If `TELOXIDE_API_URL` is set, the bot talks
+/// to the Bot API server at that URL instead of Telegram's
 pub async fn start_mafia_bot() -> Result<(), Box<dyn Error>> {
     pretty_env_logger::init();
     log::info!("Starting That Mafia Game Bot");
 
-    let bot_state = new_async_bot_state();
+    let mut bot = Bot::from_env();
+    if let Ok(api_url) = env::var("TELOXIDE_API_URL") {
+        bot = bot.set_api_url(api_url.parse()?);
+    }
+
+    run_mafia_bot(bot, new_async_bot_state()).await;
+
+    Ok(())
+}
 
+/// Resumes the saved games, then handles updates until the bot is stopped
+pub async fn run_mafia_bot(bot: Bot, bot_state: AsyncBotState) {
     let handler = dptree::entry()
         .branch(get_game_handler())
         .branch(get_lobby_handler())
         .branch(get_main_menu_handler());
 
-    let bot = Bot::from_env();
+    resume_games(bot.clone(), bot_state.clone()).await;
+
     Dispatcher::builder(bot, handler)
         .dependencies(dptree::deps![bot_state])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
         .await;
-
-    Ok(())
 }
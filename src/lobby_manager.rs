@@ -1,32 +1,65 @@
-use teloxide::prelude::*;
+use crate::game::{game_setup::GameSetup, PlayerId};
+use serde::{Deserialize, Serialize};
 
 pub mod local_lobby_manager;
+pub mod sqlite_lobby_manager;
 
-#[derive(Eq, Hash, PartialEq, Copy, Clone, derive_more::Display)]
+#[derive(Eq, Hash, PartialEq, Copy, Clone, derive_more::Display, Serialize, Deserialize)]
 pub struct LobbyId(pub i32);
 
+#[derive(Serialize, Deserialize)]
 pub struct User {
-    pub chat_id: ChatId,
+    pub player_id: PlayerId,
     pub username: String,
 }
 
 // Provides a snapshot of a lobby's details
+#[derive(Serialize, Deserialize)]
 pub struct Lobby {
-    pub host_id: ChatId,
+    pub host_id: PlayerId,
     pub users: Vec<User>,
     pub lobby_id: LobbyId,
+    /// The rules the lobby's game will be created with
+    #[serde(default)]
+    pub setup: GameSetup,
 }
 
-pub trait LobbyManager {
-    // Gets the instantaneous lobby, if present, of a chat user.
-    fn get_chats_lobby(&self, chat_id: ChatId) -> Option<&Lobby>;
+impl Lobby {
+    /// Makes a lobby of Player1 to Player<player_count>, hosted by Player1, for games that are
+    /// not played through a lobby manager, e.g. local games and tests
+    pub fn with_players(player_count: usize, setup: GameSetup) -> Lobby {
+        Lobby {
+            host_id: PlayerId(1),
+            users: (1..=player_count as i64)
+                .map(|i| User {
+                    player_id: PlayerId(i),
+                    username: format!("Player{i}"),
+                })
+                .collect(),
+            lobby_id: LobbyId(0),
+            setup,
+        }
+    }
+}
+
+pub trait LobbyManager: Send {
+    // Gets the instantaneous lobby, if present, of a player.
+    fn get_players_lobby(&self, player_id: PlayerId) -> Option<&Lobby>;
 
     fn create_lobby(&mut self, user: User) -> Result<&Lobby, &'static str>;
 
     fn join_lobby(&mut self, lobby_id: LobbyId, user: User) -> Result<&Lobby, &'static str>;
 
+    /// Replaces the setup that the lobby's game will be created with
+    fn set_lobby_setup(
+        &mut self,
+        lobby_id: LobbyId,
+        setup: GameSetup,
+    ) -> Result<&Lobby, &'static str>;
+
     fn close_lobby(&mut self, lobby_id: LobbyId) -> Result<(), &'static str>;
 
-    // If the host quits, then a remaining player should be randomly chosen to be the new host
-    fn quit_lobby(&mut self, chat_id: ChatId) -> Result<LobbyId, &'static str>;
+    /// Removes the player from their lobby. If the host quits, the next player in the lobby becomes
+    /// the new host, and a lobby that nobody is left in is closed
+    fn quit_lobby(&mut self, player_id: PlayerId) -> Result<LobbyId, &'static str>;
 }
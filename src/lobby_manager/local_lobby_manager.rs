@@ -1,63 +1,102 @@
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::collections::HashMap;
 
-use crate::lobby_manager::*;
+use crate::{game::game_setup::GameSetup, lobby_manager::*};
 
 pub struct LocalLobbyManager {
     lobbies: HashMap<LobbyId, Lobby>,
-    user_map: HashMap<ChatId, LobbyId>,
+    user_map: HashMap<PlayerId, LobbyId>,
+    rng: StdRng,
 }
 
 impl LocalLobbyManager {
     pub fn new() -> LocalLobbyManager {
+        LocalLobbyManager::with_rng(StdRng::from_entropy())
+    }
+
+    /// Creates a manager whose lobby codes are drawn from `rng`, e.g. a seeded one
+    pub fn with_rng(rng: StdRng) -> LocalLobbyManager {
         LocalLobbyManager {
             lobbies: HashMap::new(),
             user_map: HashMap::new(),
+            rng,
         }
     }
+
+    pub fn get_lobby(&self, lobby_id: LobbyId) -> Option<&Lobby> {
+        self.lobbies.get(&lobby_id)
+    }
+
+    /// Adds a lobby under its existing lobby_id, e.g. when restoring a saved lobby
+    pub fn insert_lobby(&mut self, lobby: Lobby) {
+        for u in lobby.users.iter() {
+            self.user_map.insert(u.player_id, lobby.lobby_id);
+        }
+        self.lobbies.insert(lobby.lobby_id, lobby);
+    }
+}
+
+impl Default for LocalLobbyManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LobbyManager for LocalLobbyManager {
-    fn get_chats_lobby(&self, chat_id: teloxide::types::ChatId) -> Option<&Lobby> {
-        let lobby_id = self.user_map.get(&chat_id)?;
+    fn get_players_lobby(&self, player_id: PlayerId) -> Option<&Lobby> {
+        let lobby_id = self.user_map.get(&player_id)?;
         self.lobbies.get(lobby_id)
     }
 
     fn create_lobby(&mut self, user: User) -> Result<&Lobby, &'static str> {
-        if let Some(_) = self.get_chats_lobby(user.chat_id) {
+        if self.get_players_lobby(user.player_id).is_some() {
             return Err("User is already in a lobby");
         }
 
-        let mut rng = rand::thread_rng();
-        let mut lobby_id = LobbyId(rng.gen_range(1_000..10_000));
-        while let Some(_) = self.lobbies.get(&lobby_id) {
-            lobby_id = LobbyId(rng.gen_range(1_000..10_000));
+        let mut lobby_id = LobbyId(self.rng.gen_range(1_000..10_000));
+        while self.lobbies.contains_key(&lobby_id) {
+            lobby_id = LobbyId(self.rng.gen_range(1_000..10_000));
         }
-        let chat_id = user.chat_id.clone();
+        let player_id = user.player_id;
 
         let lobby = Lobby {
-            host_id: user.chat_id,
+            host_id: user.player_id,
             users: vec![user],
             lobby_id,
+            setup: GameSetup::default(),
         };
 
         self.lobbies.insert(lobby_id, lobby);
-        self.user_map.insert(chat_id, lobby_id);
+        self.user_map.insert(player_id, lobby_id);
 
-        return Ok(self.lobbies.get(&lobby_id).unwrap());
+        Ok(self.lobbies.get(&lobby_id).unwrap())
     }
 
     fn join_lobby(&mut self, lobby_id: LobbyId, user: User) -> Result<&Lobby, &'static str> {
-        if let Some(_) = self.get_chats_lobby(user.chat_id) {
+        if self.get_players_lobby(user.player_id).is_some() {
             return Err("User is already in a lobby");
         }
 
-        let chat_id = user.chat_id;
+        let player_id = user.player_id;
 
         match self.lobbies.get_mut(&lobby_id) {
             Some(lobby) => {
                 lobby.users.push(user);
-                self.user_map.insert(chat_id, lobby.lobby_id);
+                self.user_map.insert(player_id, lobby.lobby_id);
+                Ok(lobby)
+            }
+            None => Err("Lobby does not exist"),
+        }
+    }
+
+    fn set_lobby_setup(
+        &mut self,
+        lobby_id: LobbyId,
+        setup: GameSetup,
+    ) -> Result<&Lobby, &'static str> {
+        match self.lobbies.get_mut(&lobby_id) {
+            Some(lobby) => {
+                lobby.setup = setup;
                 Ok(lobby)
             }
             None => Err("Lobby does not exist"),
@@ -67,32 +106,32 @@ impl LobbyManager for LocalLobbyManager {
     fn close_lobby(&mut self, lobby_id: LobbyId) -> Result<(), &'static str> {
         let users = self.lobbies.get(&lobby_id).unwrap().users.iter();
         for p in users {
-            self.user_map.remove(&p.chat_id);
+            self.user_map.remove(&p.player_id);
         }
         self.lobbies.remove(&lobby_id);
 
         Ok(())
     }
 
-    fn quit_lobby(&mut self, chat_id: ChatId) -> Result<LobbyId, &'static str> {
-        if let Some(lobby_id) = self.user_map.get(&chat_id) {
-            if let Some(lobby) = self.lobbies.get_mut(&lobby_id) {
-                if lobby.users.len() == 1 {
+    fn quit_lobby(&mut self, player_id: PlayerId) -> Result<LobbyId, &'static str> {
+        if let Some(lobby_id) = self.user_map.get(&player_id) {
+            if let Some(lobby) = self.lobbies.get_mut(lobby_id) {
+                lobby.users.retain(|p| p.player_id != player_id);
+                if lobby.users.is_empty() {
                     self.lobbies.remove(lobby_id);
-                } else if lobby.host_id == chat_id {
-                    lobby.users.retain(|p| p.chat_id != chat_id);
-                    lobby.host_id = lobby.users[0].chat_id;
+                } else if lobby.host_id == player_id {
+                    lobby.host_id = lobby.users[0].player_id;
                 }
 
-                let ret = lobby_id.clone();
-                self.user_map.remove(&chat_id);
+                let ret = *lobby_id;
+                self.user_map.remove(&player_id);
 
                 Ok(ret)
             } else {
                 Err("Internal error: user_map and lobbies not synced")
             }
         } else {
-            Err("Chat ID is not in any lobby")
+            Err("Player ID is not in any lobby")
         }
     }
 }
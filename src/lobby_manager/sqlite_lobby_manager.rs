@@ -0,0 +1,109 @@
+use rand::rngs::StdRng;
+use rusqlite::{params, Connection};
+
+use super::local_lobby_manager::LocalLobbyManager;
+use crate::{game::game_setup::GameSetup, lobby_manager::*};
+
+/// A `LobbyManager` that keeps lobbies in memory and writes them through to a SQLite database, so
+/// that open lobbies survive a restart
+pub struct SqliteLobbyManager {
+    lobbies: LocalLobbyManager,
+    conn: Connection,
+}
+
+impl SqliteLobbyManager {
+    /// Opens the database at `path`, restoring the lobbies that were saved in it. New lobby codes
+    /// are drawn from `rng`
+    pub fn open(path: &str, rng: StdRng) -> rusqlite::Result<SqliteLobbyManager> {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS lobbies (
+                lobby_id INTEGER PRIMARY KEY,
+                state TEXT NOT NULL
+            )",
+            (),
+        )?;
+
+        let mut lobbies = LocalLobbyManager::with_rng(rng);
+        {
+            let mut stmt = conn.prepare("SELECT lobby_id, state FROM lobbies")?;
+            let rows = stmt.query_map((), |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+            })?;
+
+            for row in rows {
+                let (lobby_id, state) = row?;
+                match serde_json::from_str::<Lobby>(&state) {
+                    Ok(lobby) => lobbies.insert_lobby(lobby),
+                    Err(err) => log::error!("Failed to restore lobby {lobby_id}: {err}"),
+                }
+            }
+        }
+
+        Ok(SqliteLobbyManager { lobbies, conn })
+    }
+}
+
+/// Saves the lobby, or deletes it if it no longer exists
+fn sync_lobby(conn: &Connection, lobby_id: LobbyId, lobby_opt: Option<&Lobby>) {
+    let res = match lobby_opt {
+        Some(lobby) => match serde_json::to_string(lobby) {
+            Ok(state) => conn.execute(
+                "INSERT OR REPLACE INTO lobbies (lobby_id, state) VALUES (?1, ?2)",
+                params![lobby_id.0, state],
+            ),
+            Err(err) => {
+                log::error!("Failed to serialize lobby {lobby_id}: {err}");
+                return;
+            }
+        },
+        None => conn.execute(
+            "DELETE FROM lobbies WHERE lobby_id = ?1",
+            params![lobby_id.0],
+        ),
+    };
+
+    if let Err(err) = res {
+        log::error!("Failed to save lobby {lobby_id}: {err}");
+    }
+}
+
+impl LobbyManager for SqliteLobbyManager {
+    fn get_players_lobby(&self, player_id: PlayerId) -> Option<&Lobby> {
+        self.lobbies.get_players_lobby(player_id)
+    }
+
+    fn create_lobby(&mut self, user: User) -> Result<&Lobby, &'static str> {
+        let lobby = self.lobbies.create_lobby(user)?;
+        sync_lobby(&self.conn, lobby.lobby_id, Some(lobby));
+        Ok(lobby)
+    }
+
+    fn join_lobby(&mut self, lobby_id: LobbyId, user: User) -> Result<&Lobby, &'static str> {
+        let lobby = self.lobbies.join_lobby(lobby_id, user)?;
+        sync_lobby(&self.conn, lobby_id, Some(lobby));
+        Ok(lobby)
+    }
+
+    fn set_lobby_setup(
+        &mut self,
+        lobby_id: LobbyId,
+        setup: GameSetup,
+    ) -> Result<&Lobby, &'static str> {
+        let lobby = self.lobbies.set_lobby_setup(lobby_id, setup)?;
+        sync_lobby(&self.conn, lobby_id, Some(lobby));
+        Ok(lobby)
+    }
+
+    fn close_lobby(&mut self, lobby_id: LobbyId) -> Result<(), &'static str> {
+        self.lobbies.close_lobby(lobby_id)?;
+        sync_lobby(&self.conn, lobby_id, None);
+        Ok(())
+    }
+
+    fn quit_lobby(&mut self, player_id: PlayerId) -> Result<LobbyId, &'static str> {
+        let lobby_id = self.lobbies.quit_lobby(player_id)?;
+        sync_lobby(&self.conn, lobby_id, self.lobbies.get_lobby(lobby_id));
+        Ok(lobby_id)
+    }
+}
@@ -0,0 +1,214 @@
+use rand::{rngs::StdRng, SeedableRng};
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    game::{
+        game_setup::GameSetup, game_v1::GameV1, Game, GameError, GamePhase, Player, PlayerId, Role,
+    },
+    lobby_manager::Lobby,
+};
+use policies::Policy;
+
+/// Plays games between scripted players, so that role balance can be tuned from data
+pub struct Simulator {
+    pub player_count: usize,
+    pub setup: GameSetup,
+    /// The policy played by every Mafia
+    pub mafia_policy: Box<dyn Policy>,
+    /// The policy played by every player that is not a Mafia
+    pub town_policy: Box<dyn Policy>,
+    /// Games that have not ended after this many phases are given up on
+    pub max_phases: usize,
+}
+
+/// The result of one simulated game
+pub struct GameReport {
+    pub seed: u64,
+    /// The winning faction, or `None` if the game was given up on
+    pub winner: Option<String>,
+    /// The number of phases that ended before the game did
+    pub phase_count: usize,
+    /// How many times the game moved from one phase to another, by phase name
+    pub transitions: BTreeMap<(&'static str, &'static str), usize>,
+}
+
+/// The combined results of many simulated games
+#[derive(Default)]
+pub struct SimulationReport {
+    pub game_count: usize,
+    /// Games won by each faction
+    pub wins: BTreeMap<String, usize>,
+    /// Games that were given up on after `Simulator::max_phases`
+    pub unfinished_count: usize,
+    /// The number of phases played over all finished games
+    pub finished_phase_count: usize,
+    pub transitions: BTreeMap<(&'static str, &'static str), usize>,
+}
+
+pub fn get_phase_name(phase: &GamePhase) -> &'static str {
+    match phase {
+        GamePhase::Night { .. } => "Night",
+        GamePhase::Voting { .. } => "Voting",
+        GamePhase::Defense { .. } => "Defense",
+        GamePhase::Trial { .. } => "Trial",
+        GamePhase::LastWords { .. } => "LastWords",
+        GamePhase::Ending => "Ending",
+    }
+}
+
+fn get_ids(players: Result<Vec<&Player>, GameError>) -> Vec<PlayerId> {
+    players
+        .unwrap_or_default()
+        .iter()
+        .map(|p| p.player_id)
+        .collect()
+}
+
+impl Simulator {
+    fn get_policy(&self, player: &Player) -> &dyn Policy {
+        if player.role == Role::Mafia {
+            self.mafia_policy.as_ref()
+        } else {
+            self.town_policy.as_ref()
+        }
+    }
+
+    /// Plays one game. The same seed always plays out the same game
+    pub fn run_game(&self, seed: u64) -> GameReport {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let lobby = Lobby::with_players(self.player_count, self.setup.clone());
+        let mut game = GameV1::from_lobby(&lobby, seed);
+
+        let mut report = GameReport {
+            seed,
+            winner: None,
+            phase_count: 0,
+            transitions: BTreeMap::new(),
+        };
+        loop {
+            if let GamePhase::Ending = game.get_phase() {
+                report.winner = game.get_winner();
+                break;
+            }
+            if report.phase_count >= self.max_phases {
+                break;
+            }
+
+            let from = get_phase_name(game.get_phase());
+            // Players are visited in a fixed order so that the game only depends on the seed
+            let mut players = game.get_players().into_iter().cloned().collect::<Vec<_>>();
+            players.sort_by_key(|p| p.player_id.0);
+
+            match game.get_phase() {
+                GamePhase::Night { .. } => {
+                    for player in players {
+                        let Some((_, options)) = game.get_night_actions().remove(&player.player_id)
+                        else {
+                            continue;
+                        };
+                        if options.is_empty() {
+                            continue;
+                        }
+                        let choice = self
+                            .get_policy(&player)
+                            .choose_night_action(&game, &player, &options, &mut rng);
+                        if let Some(target_id) = choice.and_then(|i| options.get(i)) {
+                            game.add_night_action(player.player_id, target_id.0).ok();
+                        }
+                    }
+                }
+                GamePhase::Voting { .. } => {
+                    let options = game.get_vote_options().unwrap_or_default();
+                    let voter_ids = get_ids(game.get_voters());
+                    for player in players.iter().filter(|p| voter_ids.contains(&p.player_id)) {
+                        let choices = self
+                            .get_policy(player)
+                            .choose_votes(&game, player, &options, &mut rng);
+                        let choices = choices.into_iter().map(|i| i as i32).collect();
+                        game.add_vote(player.player_id, choices).ok();
+                    }
+                }
+                GamePhase::Trial { .. } => {
+                    let options = game.get_verdict_options().unwrap_or_default();
+                    let juror_ids = get_ids(game.get_jury());
+                    for player in players.iter().filter(|p| juror_ids.contains(&p.player_id)) {
+                        let choice = self
+                            .get_policy(player)
+                            .choose_verdict(&game, player, &options, &mut rng);
+                        if let Some(i) = choice {
+                            game.add_verdict(player.player_id, i as i32).ok();
+                        }
+                    }
+                }
+                GamePhase::Defense { .. } | GamePhase::LastWords { .. } | GamePhase::Ending => {}
+            }
+
+            game.end_or_force_phase();
+            let to = get_phase_name(game.get_phase());
+            *report.transitions.entry((from, to)).or_default() += 1;
+            report.phase_count += 1;
+        }
+
+        report
+    }
+
+    /// Plays `game_count` games, seeded with consecutive seeds starting from `first_seed`
+    pub fn run_games(&self, game_count: usize, first_seed: u64) -> SimulationReport {
+        let mut summary = SimulationReport::default();
+        for seed in (first_seed..).take(game_count) {
+            summary.add_game(self.run_game(seed));
+        }
+        summary
+    }
+}
+
+impl SimulationReport {
+    pub fn add_game(&mut self, report: GameReport) {
+        self.game_count += 1;
+        match report.winner {
+            Some(winner) => {
+                *self.wins.entry(winner).or_default() += 1;
+                self.finished_phase_count += report.phase_count;
+            }
+            None => self.unfinished_count += 1,
+        }
+        for (transition, count) in report.transitions {
+            *self.transitions.entry(transition).or_default() += count;
+        }
+    }
+
+    /// The average number of phases in a finished game
+    pub fn get_average_length(&self) -> f64 {
+        let finished_count = self.game_count - self.unfinished_count;
+        if finished_count == 0 {
+            0.0
+        } else {
+            self.finished_phase_count as f64 / finished_count as f64
+        }
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |count: usize| 100.0 * count as f64 / self.game_count.max(1) as f64;
+
+        writeln!(f, "Games played: {}", self.game_count)?;
+        for (faction, wins) in self.wins.iter() {
+            writeln!(f, "{faction} won: {wins} ({:.1}%)", percent(*wins))?;
+        }
+        writeln!(
+            f,
+            "Unfinished: {} ({:.1}%)",
+            self.unfinished_count,
+            percent(self.unfinished_count)
+        )?;
+        writeln!(f, "Average length: {:.1} phases", self.get_average_length())?;
+        writeln!(f, "Phase transitions:")?;
+        for ((from, to), count) in self.transitions.iter() {
+            writeln!(f, "  {from} -> {to}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+pub mod policies;
@@ -0,0 +1,212 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+
+use crate::game::{
+    Game, GamePhase, Player, PlayerId, Role, Verdict, NOBODY_PLAYER_ID, VOTE_OPTION_NOBODY,
+};
+
+/// Decides a scripted player's inputs. Every choice is an index into the given options, and
+/// `None` skips the choice as if the player never answered
+pub trait Policy {
+    fn choose_night_action(
+        &self,
+        game: &dyn Game,
+        player: &Player,
+        options: &[(PlayerId, String)],
+        rng: &mut StdRng,
+    ) -> Option<usize>;
+
+    fn choose_votes(
+        &self,
+        game: &dyn Game,
+        voter: &Player,
+        options: &[(PlayerId, String)],
+        rng: &mut StdRng,
+    ) -> Vec<usize>;
+
+    fn choose_verdict(
+        &self,
+        game: &dyn Game,
+        juror: &Player,
+        options: &[Verdict],
+        rng: &mut StdRng,
+    ) -> Option<usize>;
+}
+
+/// Picks every option at random, never choosing nobody when a player can be picked
+pub struct RandomPolicy;
+
+/// Never targets, votes for or convicts anybody
+pub struct NobodyPolicy;
+
+/// Mafia all target and vote for the same non-Mafia player, and acquit each other. Players that
+/// are not Mafia play like `RandomPolicy`
+pub struct MafiaCoordinatedPolicy;
+
+/// Returns the index of a random option that is not nobody
+fn choose_random_player(options: &[(PlayerId, String)], rng: &mut StdRng) -> Option<usize> {
+    let indices = options
+        .iter()
+        .enumerate()
+        .filter(|(_, (player_id, _))| *player_id != NOBODY_PLAYER_ID)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    indices.choose(rng).copied()
+}
+
+fn find_option<T: PartialEq>(options: &[T], option: T) -> Option<usize> {
+    options.iter().position(|o| *o == option)
+}
+
+fn is_mafia(game: &dyn Game, player_id: PlayerId) -> bool {
+    game.get_players()
+        .iter()
+        .any(|p| p.player_id == player_id && p.role == Role::Mafia)
+}
+
+impl Policy for RandomPolicy {
+    fn choose_night_action(
+        &self,
+        _game: &dyn Game,
+        _player: &Player,
+        options: &[(PlayerId, String)],
+        rng: &mut StdRng,
+    ) -> Option<usize> {
+        choose_random_player(options, rng)
+    }
+
+    fn choose_votes(
+        &self,
+        _game: &dyn Game,
+        _voter: &Player,
+        options: &[(PlayerId, String)],
+        rng: &mut StdRng,
+    ) -> Vec<usize> {
+        choose_random_player(options, rng).into_iter().collect()
+    }
+
+    fn choose_verdict(
+        &self,
+        _game: &dyn Game,
+        _juror: &Player,
+        options: &[Verdict],
+        rng: &mut StdRng,
+    ) -> Option<usize> {
+        Some(rng.gen_range(0..options.len()))
+    }
+}
+
+impl Policy for NobodyPolicy {
+    fn choose_night_action(
+        &self,
+        _game: &dyn Game,
+        _player: &Player,
+        options: &[(PlayerId, String)],
+        _rng: &mut StdRng,
+    ) -> Option<usize> {
+        options
+            .iter()
+            .position(|(player_id, _)| *player_id == NOBODY_PLAYER_ID)
+    }
+
+    fn choose_votes(
+        &self,
+        _game: &dyn Game,
+        _voter: &Player,
+        options: &[(PlayerId, String)],
+        _rng: &mut StdRng,
+    ) -> Vec<usize> {
+        options
+            .iter()
+            .position(|(player_id, _)| *player_id == VOTE_OPTION_NOBODY)
+            .into_iter()
+            .collect()
+    }
+
+    fn choose_verdict(
+        &self,
+        _game: &dyn Game,
+        _juror: &Player,
+        options: &[Verdict],
+        _rng: &mut StdRng,
+    ) -> Option<usize> {
+        find_option(options, Verdict::Innocent)
+    }
+}
+
+impl MafiaCoordinatedPolicy {
+    /// The Mafia's shared target: the living non-Mafia option with the lowest id
+    fn choose_team_target(game: &dyn Game, options: &[(PlayerId, String)]) -> Option<usize> {
+        options
+            .iter()
+            .enumerate()
+            .filter(|(_, (player_id, _))| {
+                *player_id != NOBODY_PLAYER_ID && !is_mafia(game, *player_id)
+            })
+            .min_by_key(|(_, (player_id, _))| player_id.0)
+            .map(|(i, _)| i)
+    }
+}
+
+impl Policy for MafiaCoordinatedPolicy {
+    fn choose_night_action(
+        &self,
+        game: &dyn Game,
+        player: &Player,
+        options: &[(PlayerId, String)],
+        rng: &mut StdRng,
+    ) -> Option<usize> {
+        match player.role {
+            Role::Mafia => MafiaCoordinatedPolicy::choose_team_target(game, options),
+            _ => RandomPolicy.choose_night_action(game, player, options, rng),
+        }
+    }
+
+    fn choose_votes(
+        &self,
+        game: &dyn Game,
+        voter: &Player,
+        options: &[(PlayerId, String)],
+        rng: &mut StdRng,
+    ) -> Vec<usize> {
+        match voter.role {
+            Role::Mafia => MafiaCoordinatedPolicy::choose_team_target(game, options)
+                .into_iter()
+                .collect(),
+            _ => RandomPolicy.choose_votes(game, voter, options, rng),
+        }
+    }
+
+    fn choose_verdict(
+        &self,
+        game: &dyn Game,
+        juror: &Player,
+        options: &[Verdict],
+        rng: &mut StdRng,
+    ) -> Option<usize> {
+        if juror.role != Role::Mafia {
+            return RandomPolicy.choose_verdict(game, juror, options, rng);
+        }
+
+        let defendant_is_mafia = match game.get_phase() {
+            GamePhase::Trial { defendant_id, .. } => is_mafia(game, *defendant_id),
+            _ => false,
+        };
+        if defendant_is_mafia {
+            find_option(options, Verdict::Innocent)
+        } else {
+            find_option(options, Verdict::Guilty)
+        }
+    }
+}
+
+/// Returns the policy with the given name, as used by the simulator's command line
+pub fn get_policy_by_name(name: &str) -> Result<Box<dyn Policy>, String> {
+    match name {
+        "random" => Ok(Box::new(RandomPolicy)),
+        "nobody" => Ok(Box::new(NobodyPolicy)),
+        "coordinated" => Ok(Box::new(MafiaCoordinatedPolicy)),
+        _ => Err(format!(
+            "Unknown policy \"{name}\", expected random, nobody or coordinated"
+        )),
+    }
+}
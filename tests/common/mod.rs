@@ -0,0 +1,77 @@
+//! Builds games and drives their phases for the game's integration tests. Each test file only
+//! uses some of these helpers.
+#![allow(dead_code)]
+
+use serde_json::Value;
+use that_mafia_game::{
+    game::{game_v1::GameV1, Game, Player, PlayerId, Role},
+    lobby_manager::Lobby,
+};
+
+/// Makes a lobby of Player1 to Player<player_count>, hosted by Player1, with the given setup
+/// options applied like the lobby's `/set` command
+pub fn make_lobby(player_count: usize, options: &[(&str, &str)]) -> Lobby {
+    let mut lobby = Lobby::with_players(player_count, Default::default());
+    for (option, value) in options {
+        lobby.setup.set_option(option, value, player_count).unwrap();
+    }
+    lobby
+}
+
+/// Starts a day-start game between Player1 to Player5, with a single Mafia and a single vote
+/// each, and then the given setup options
+pub fn start_game(options: &[(&str, &str)]) -> GameV1 {
+    let mut all_options = vec![("mafia", "1"), ("start", "day"), ("multivote", "off")];
+    all_options.extend_from_slice(options);
+    GameV1::from_lobby(&make_lobby(5, &all_options), 0)
+}
+
+pub fn get_ids(players: Vec<&Player>) -> Vec<PlayerId> {
+    players.iter().map(|p| p.player_id).collect()
+}
+
+pub fn mafia_id(game: &GameV1) -> PlayerId {
+    game.get_players()
+        .iter()
+        .find(|p| p.role == Role::Mafia)
+        .unwrap()
+        .player_id
+}
+
+/// Returns the players that are not the Mafia, in player order
+pub fn civilian_ids(game: &GameV1) -> Vec<PlayerId> {
+    game.get_players()
+        .iter()
+        .filter(|p| p.role != Role::Mafia)
+        .map(|p| p.player_id)
+        .collect()
+}
+
+pub fn vote(game: &mut GameV1, voter_id: PlayerId, target_id: PlayerId) {
+    let option = game
+        .get_vote_options()
+        .unwrap()
+        .iter()
+        .position(|(id, _)| *id == target_id)
+        .unwrap();
+    game.add_vote(voter_id, vec![option as i32]).unwrap();
+}
+
+/// Has every player vote for the defendant, which ends the voting
+pub fn put_on_trial(game: &mut GameV1, defendant_id: PlayerId) {
+    for voter_id in get_ids(game.get_voters().unwrap()) {
+        vote(game, voter_id, defendant_id);
+    }
+    game.end_phase().unwrap();
+}
+
+/// Has every juror give the verdict at the given index, e.g. 0 for Guilty
+pub fn give_verdicts(game: &mut GameV1, verdict: i32) {
+    for juror_id in get_ids(game.get_jury().unwrap()) {
+        game.add_verdict(juror_id, verdict).unwrap();
+    }
+}
+
+pub fn to_value(game: &GameV1) -> Value {
+    serde_json::from_str(&game.to_json().unwrap()).unwrap()
+}
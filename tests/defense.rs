@@ -0,0 +1,95 @@
+//! Checks the defendant's defense before their trial, and a lynched player's last words
+
+use that_mafia_game::game::{game_v1::GameV1, ChatChannel, ChatRoute, Game, GamePhase, PlayerId};
+
+use common::{civilian_ids, get_ids, give_verdicts, put_on_trial};
+
+mod common;
+
+fn start_game() -> GameV1 {
+    common::start_game(&[("defense", "on"), ("lastwords", "on")])
+}
+
+/// Puts the first player who is not the Mafia on trial, returning that player. Their lynch does
+/// not decide the game
+fn put_civilian_on_trial(game: &mut GameV1) -> PlayerId {
+    let defendant_id = civilian_ids(game)[0];
+    put_on_trial(game, defendant_id);
+    defendant_id
+}
+
+fn relays_to_everyone(game: &GameV1, sender_id: PlayerId) -> bool {
+    match game.route_chat_message(sender_id) {
+        ChatRoute::Relay {
+            channel: ChatChannel::Day,
+            recipients,
+        } => recipients.len() == 4 && !recipients.contains(&sender_id),
+        _ => false,
+    }
+}
+
+#[test]
+fn only_the_defendant_speaks_before_their_trial() {
+    let mut game = start_game();
+    let defendant_id = put_civilian_on_trial(&mut game);
+    assert!(matches!(
+        game.get_phase(),
+        GamePhase::Defense { defendant_id: id } if *id == defendant_id
+    ));
+    assert!(game.get_idle_players().is_empty());
+
+    assert!(relays_to_everyone(&game, defendant_id));
+    for player_id in get_ids(game.get_players())
+        .into_iter()
+        .filter(|id| *id != defendant_id)
+    {
+        assert!(matches!(
+            game.route_chat_message(player_id),
+            ChatRoute::Reject { reason } if reason.contains("during their defense")
+        ));
+    }
+
+    // The defense lasts until it is forced to end
+    assert!(game.end_phase().is_none());
+    assert!(matches!(
+        game.force_end_phase(),
+        Some(GamePhase::Trial { defendant_id: id, .. }) if *id == defendant_id
+    ));
+    assert_eq!(
+        game.get_transition_message(),
+        format!("Now begins the trial for Player{defendant_id}:")
+    );
+}
+
+#[test]
+fn a_lynched_player_says_their_last_words_before_the_night() {
+    let mut game = start_game();
+    let defendant_id = put_civilian_on_trial(&mut game);
+    game.force_end_phase().unwrap();
+    give_verdicts(&mut game, 0);
+    assert!(matches!(
+        game.end_phase(),
+        Some(GamePhase::LastWords { player_id }) if *player_id == defendant_id
+    ));
+    assert!(game.get_transition_message().ends_with(&format!(
+        "Player{defendant_id} may now say their last words"
+    )));
+    assert!(game.get_private_messages()[&defendant_id][0].starts_with("You have been lynched"));
+
+    assert!(relays_to_everyone(&game, defendant_id));
+    assert!(game.end_phase().is_none());
+
+    // The player joins the graveyard once their last words are over
+    assert!(matches!(
+        game.force_end_phase(),
+        Some(GamePhase::Night { .. })
+    ));
+    assert!(game.get_private_messages()[&defendant_id][0].starts_with("You are dead"));
+    assert!(matches!(
+        game.route_chat_message(defendant_id),
+        ChatRoute::Reject { .. }
+    ));
+    assert!(game
+        .get_summary()
+        .contains(&format!("Day 1 trial of Player{defendant_id}: lynched")));
+}
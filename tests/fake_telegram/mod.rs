@@ -0,0 +1,428 @@
+//! A local stand-in for the Telegram Bot API. It records the requests the bot makes and feeds it
+//! scripted updates through `getUpdates`, so that the handlers can be tested without a network.
+//!
+//! Every player talks to the bot from a private chat whose id is their user id, and is named
+//! `player<id>`.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    routing::post,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use teloxide::Bot;
+use tokio::{sync::Notify, time::Instant};
+
+/// How long `wait_for` waits for the bot before failing the test
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a `getUpdates` request is held open when there are no updates
+const POLL_TIMEOUT: Duration = Duration::from_millis(500);
+
+const BOT_USER_ID: i64 = 1_000_000;
+
+#[derive(Clone)]
+pub struct FakeTelegram {
+    addr: SocketAddr,
+    inner: Arc<Mutex<Inner>>,
+    update_added: Arc<Notify>,
+}
+
+/// A Bot API call made by the bot
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub params: Value,
+    /// The result sent back to the bot, e.g. the sent message
+    pub result: Value,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Updates that the bot has not confirmed yet, oldest first
+    updates: Vec<Value>,
+    next_update_id: i64,
+    next_message_id: i64,
+    next_poll_id: i64,
+    next_callback_id: i64,
+    /// Every message sent by the bot, by chat id and message id
+    messages: HashMap<(i64, i64), Value>,
+    /// Users who blocked the bot, so that it can no longer send them anything
+    blocked_ids: HashSet<i64>,
+    /// Methods that fail whenever the bot calls them, e.g. to simulate a network error
+    failing_methods: HashSet<String>,
+    requests: Vec<Request>,
+}
+
+impl Request {
+    pub fn chat_id(&self) -> i64 {
+        self.params["chat_id"].as_i64().unwrap_or_default()
+    }
+
+    pub fn text(&self) -> &str {
+        self.params["text"].as_str().unwrap_or_default()
+    }
+
+    /// Whether this is a message or poll sent to `chat_id` whose text contains `text`
+    pub fn is_message_to(&self, chat_id: i64, text: &str) -> bool {
+        let content = match self.method.as_str() {
+            "sendMessage" => self.text(),
+            "sendPoll" => self.params["question"].as_str().unwrap_or_default(),
+            _ => return false,
+        };
+        self.chat_id() == chat_id && content.contains(text)
+    }
+
+    /// The texts of the buttons on a message's inline keyboard, or of a poll's options
+    pub fn option_texts(&self) -> Vec<String> {
+        let options = match self.method.as_str() {
+            "sendPoll" => self.params["options"].as_array().cloned(),
+            _ => self.params["reply_markup"]["inline_keyboard"]
+                .as_array()
+                .map(|rows| rows.iter().map(|row| row[0]["text"].clone()).collect()),
+        };
+        options
+            .unwrap_or_default()
+            .iter()
+            .map(|o| o.as_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    /// The callback data of the button with the given text
+    pub fn button_data(&self, button_text: &str) -> String {
+        self.params["reply_markup"]["inline_keyboard"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|row| &row[0])
+            .find(|button| button["text"] == button_text)
+            .and_then(|button| button["callback_data"].as_str())
+            .unwrap_or_else(|| panic!("No button \"{button_text}\" in {:?}", self.params))
+            .to_string()
+    }
+}
+
+fn user(user_id: i64) -> Value {
+    json!({
+        "id": user_id,
+        "is_bot": false,
+        "first_name": format!("Player{user_id}"),
+        "username": format!("player{user_id}"),
+    })
+}
+
+fn private_chat(user_id: i64) -> Value {
+    json!({
+        "id": user_id,
+        "type": "private",
+        "first_name": format!("Player{user_id}"),
+        "username": format!("player{user_id}"),
+    })
+}
+
+fn ok(result: Value) -> Json<Value> {
+    Json(json!({ "ok": true, "result": result }))
+}
+
+fn error(description: &str) -> Json<Value> {
+    Json(json!({ "ok": false, "error_code": 400, "description": description }))
+}
+
+fn forbidden(description: &str) -> Json<Value> {
+    Json(json!({ "ok": false, "error_code": 403, "description": description }))
+}
+
+impl FakeTelegram {
+    /// Starts serving the fake API on a free local port
+    pub fn start() -> FakeTelegram {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind fake Telegram");
+        let telegram = FakeTelegram {
+            addr: listener.local_addr().unwrap(),
+            inner: Arc::new(Mutex::new(Inner::default())),
+            update_added: Arc::new(Notify::new()),
+        };
+
+        let app = Router::new()
+            .route("/:token/:method", post(handle_request))
+            .with_state(telegram.clone());
+        let server = axum::Server::from_tcp(listener)
+            .expect("Failed to start fake Telegram")
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        telegram
+    }
+
+    /// A bot that talks to this server instead of Telegram
+    pub fn bot(&self) -> Bot {
+        Bot::new("1234:TEST").set_api_url(format!("http://{}/", self.addr).parse().unwrap())
+    }
+
+    fn push_update(&self, kind: &str, content: Value) {
+        let mut inner = self.inner.lock().unwrap();
+        let update_id = inner.next_update_id;
+        inner.next_update_id += 1;
+        inner
+            .updates
+            .push(json!({ "update_id": update_id, kind: content }));
+        self.update_added.notify_one();
+    }
+
+    /// Has the user block the bot. Every message the bot sends them afterwards fails
+    pub fn block_bot(&self, user_id: i64) {
+        self.inner.lock().unwrap().blocked_ids.insert(user_id);
+    }
+
+    /// Makes every later call to `method` fail, as if Telegram had rejected it
+    pub fn fail_method(&self, method: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .failing_methods
+            .insert(method.to_string());
+    }
+
+    /// Sends a text message, e.g. a command, from the player's private chat
+    pub fn send_text(&self, user_id: i64, text: &str) {
+        let message_id = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.next_message_id += 1;
+            inner.next_message_id
+        };
+        self.push_update(
+            "message",
+            json!({
+                "message_id": message_id,
+                "date": 0,
+                "chat": private_chat(user_id),
+                "from": user(user_id),
+                "text": text,
+            }),
+        );
+    }
+
+    /// Presses a button on the inline keyboard of a message sent by the bot
+    pub fn press_button(&self, user_id: i64, message: &Request, button_text: &str) {
+        self.press_button_with_data(user_id, message, &message.button_data(button_text));
+    }
+
+    /// Presses a button with the given callback data on a message sent by the bot, e.g. to press
+    /// a button from an older keyboard
+    pub fn press_button_with_data(&self, user_id: i64, message: &Request, data: &str) {
+        let callback_id = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.next_callback_id += 1;
+            inner.next_callback_id
+        };
+        self.push_update(
+            "callback_query",
+            json!({
+                "id": callback_id.to_string(),
+                "from": user(user_id),
+                "message": message.result,
+                "chat_instance": "0",
+                "data": data,
+            }),
+        );
+    }
+
+    /// Answers a poll sent by the bot with the options at the given indices
+    pub fn answer_poll(&self, user_id: i64, poll: &Request, option_ids: &[i32]) {
+        self.push_update(
+            "poll_answer",
+            json!({
+                "poll_id": poll.result["poll"]["id"],
+                "user": user(user_id),
+                "option_ids": option_ids,
+            }),
+        );
+    }
+
+    /// Every request the bot has made so far, except for polling for updates
+    pub fn requests(&self) -> Vec<Request> {
+        self.inner.lock().unwrap().requests.clone()
+    }
+
+    /// Waits until the bot has made at least `count` requests matching `predicate`, returning them
+    pub async fn wait_for_all(
+        &self,
+        count: usize,
+        predicate: impl Fn(&Request) -> bool,
+    ) -> Vec<Request> {
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        loop {
+            let matches = self
+                .requests()
+                .into_iter()
+                .filter(|r| predicate(r))
+                .collect::<Vec<_>>();
+            if matches.len() >= count {
+                return matches;
+            }
+            if Instant::now() > deadline {
+                panic!(
+                    "Expected {count} matching requests, found {}. Requests: {:#?}",
+                    matches.len(),
+                    self.requests()
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Waits until the bot has made a request matching `predicate`, returning the first one
+    pub async fn wait_for(&self, predicate: impl Fn(&Request) -> bool) -> Request {
+        self.wait_for_all(1, predicate).await.remove(0)
+    }
+
+    /// Waits until the bot has sent a message or poll to `chat_id` containing `text`
+    pub async fn wait_for_message(&self, chat_id: i64, text: &str) -> Request {
+        self.wait_for(|r| r.is_message_to(chat_id, text)).await
+    }
+
+    async fn get_updates(&self, params: &Value) -> Json<Value> {
+        let offset = params["offset"].as_i64().unwrap_or_default();
+        loop {
+            let update_added = self.update_added.notified();
+            {
+                let mut inner = self.inner.lock().unwrap();
+                inner
+                    .updates
+                    .retain(|u| u["update_id"].as_i64().unwrap() >= offset);
+                if !inner.updates.is_empty() {
+                    return ok(json!(inner.updates));
+                }
+            }
+            if tokio::time::timeout(POLL_TIMEOUT, update_added)
+                .await
+                .is_err()
+            {
+                return ok(json!([]));
+            }
+        }
+    }
+
+    /// Answers the bot's call to `method`. Calls that would fail on Telegram fail here too
+    fn handle_call(&self, method: &str, params: &Value) -> Json<Value> {
+        let mut inner = self.inner.lock().unwrap();
+        let chat_id = params["chat_id"].as_i64().unwrap_or_default();
+        let message_key = (chat_id, params["message_id"].as_i64().unwrap_or_default());
+
+        let response = match method {
+            _ if inner.failing_methods.contains(method) => {
+                error(&format!("Bad Request: {method} failed"))
+            }
+            "getMe" => ok(json!({
+                "id": BOT_USER_ID,
+                "is_bot": true,
+                "first_name": "That Mafia Game",
+                "username": "that_mafia_test_bot",
+                "can_join_groups": false,
+                "can_read_all_group_messages": false,
+                "supports_inline_queries": false,
+            })),
+            "getWebhookInfo" => ok(json!({
+                "url": "",
+                "has_custom_certificate": false,
+                "pending_update_count": 0,
+            })),
+            "deleteWebhook" | "answerCallbackQuery" => ok(json!(true)),
+            "sendMessage" | "sendPoll" if inner.blocked_ids.contains(&chat_id) => {
+                forbidden("Forbidden: bot was blocked by the user")
+            }
+            "sendMessage" | "sendPoll" => {
+                inner.next_message_id += 1;
+                let message_id = inner.next_message_id;
+                let mut message = json!({
+                    "message_id": message_id,
+                    "date": 0,
+                    "chat": private_chat(chat_id),
+                    "from": {
+                        "id": BOT_USER_ID,
+                        "is_bot": true,
+                        "first_name": "That Mafia Game",
+                    },
+                });
+                if method == "sendMessage" {
+                    message["text"] = params["text"].clone();
+                    if !params["reply_markup"].is_null() {
+                        message["reply_markup"] = params["reply_markup"].clone();
+                    }
+                } else {
+                    inner.next_poll_id += 1;
+                    let options = params["options"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|text| json!({ "text": text, "voter_count": 0 }))
+                        .collect::<Vec<_>>();
+                    message["poll"] = json!({
+                        "id": inner.next_poll_id.to_string(),
+                        "question": params["question"],
+                        "options": options,
+                        "total_voter_count": 0,
+                        "is_closed": false,
+                        "is_anonymous": params["is_anonymous"].as_bool().unwrap_or(true),
+                        "type": "regular",
+                        "allows_multiple_answers":
+                            params["allows_multiple_answers"].as_bool().unwrap_or(false),
+                    });
+                }
+                inner
+                    .messages
+                    .insert((chat_id, message_id), message.clone());
+                ok(message)
+            }
+            "stopPoll" => match inner.messages.get_mut(&message_key) {
+                Some(message) if message["poll"]["is_closed"] == false => {
+                    message["poll"]["is_closed"] = json!(true);
+                    ok(message["poll"].clone())
+                }
+                Some(_) => error("Bad Request: poll has already been closed"),
+                None => error("Bad Request: message with poll to stop not found"),
+            },
+            "editMessageText" => match inner.messages.get_mut(&message_key) {
+                Some(message) => {
+                    message["text"] = params["text"].clone();
+                    message["reply_markup"] = params["reply_markup"].clone();
+                    ok(message.clone())
+                }
+                None => error("Bad Request: message to edit not found"),
+            },
+            _ => error(&format!("Not Found: method {method} is not faked")),
+        };
+
+        inner.requests.push(Request {
+            method: method.to_string(),
+            params: params.clone(),
+            result: response.0["result"].clone(),
+        });
+        response
+    }
+}
+
+async fn handle_request(
+    State(telegram): State<FakeTelegram>,
+    Path((_token, method)): Path<(String, String)>,
+    body: Bytes,
+) -> Json<Value> {
+    let params = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    // Telegram matches method names case insensitively, and teloxide sends e.g. `SendMessage`
+    let mut chars = method.chars();
+    let method = chars
+        .next()
+        .map(|first| first.to_lowercase().chain(chars).collect::<String>())
+        .unwrap_or_default();
+    if method == "getUpdates" {
+        telegram.get_updates(&params).await
+    } else {
+        telegram.handle_call(&method, &params)
+    }
+}
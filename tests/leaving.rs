@@ -0,0 +1,120 @@
+//! Checks that players can quit or hand their place to a substitute during a game
+
+use that_mafia_game::game::{game_v1::GameV1, Game, GameError, GamePhase, PlayerId, Role};
+
+use common::{civilian_ids, mafia_id, put_on_trial, start_game, vote};
+
+mod common;
+
+fn is_idle(game: &GameV1, player_id: PlayerId) -> bool {
+    game.get_idle_players()
+        .iter()
+        .any(|p| p.player_id == player_id)
+}
+
+#[test]
+fn quitting_drops_the_votes_against_the_player() {
+    let mut game = start_game(&[]);
+    let civilian_ids = civilian_ids(&game);
+    let (voter_id, quitter_id) = (civilian_ids[0], civilian_ids[1]);
+
+    let quitter_role = game
+        .get_players()
+        .iter()
+        .find(|p| p.player_id == quitter_id)
+        .unwrap()
+        .role;
+
+    vote(&mut game, voter_id, quitter_id);
+    assert!(!is_idle(&game, voter_id));
+    assert!(game.quit(quitter_id).unwrap().is_none());
+
+    // The voter has to choose again, between the players that are left
+    assert!(is_idle(&game, voter_id));
+    assert!(!game
+        .get_vote_options()
+        .unwrap()
+        .iter()
+        .any(|(id, _)| *id == quitter_id));
+    assert!(!game.get_players().iter().any(|p| p.player_id == quitter_id));
+    assert!(matches!(game.quit(quitter_id), Err(GameError::NotAlive)));
+
+    let summary = game.get_summary();
+    assert!(summary.contains(&format!(
+        "Player{quitter_id}: {quitter_role}, quit on day 1"
+    )));
+    assert!(summary.ends_with(&format!("Player{quitter_id} quit on day 1")));
+}
+
+#[test]
+fn quitting_can_decide_the_winner() {
+    let mut game = start_game(&[]);
+    let phase_number = game.get_phase_number();
+
+    let phase = game.quit(mafia_id(&game)).unwrap();
+    assert!(matches!(phase, Some(GamePhase::Ending)));
+    assert_eq!(game.get_phase_number(), phase_number + 1);
+    assert_eq!(game.get_winner().as_deref(), Some("Civilians"));
+    assert_eq!(game.get_transition_message(), "Civilians won the game!");
+}
+
+#[test]
+fn the_trial_ends_if_the_defendant_quits() {
+    // The defendant may quit during their trial, or during their defense before it
+    for options in [&[][..], &[("defense", "on")]] {
+        let mut game = start_game(options);
+        let civilian_ids = civilian_ids(&game);
+        let defendant_id = civilian_ids[0];
+        put_on_trial(&mut game, defendant_id);
+        assert!(matches!(
+            game.get_phase(),
+            GamePhase::Trial { defendant_id: id, .. } | GamePhase::Defense { defendant_id: id }
+                if *id == defendant_id
+        ));
+
+        let phase = game.quit(defendant_id).unwrap();
+        assert!(matches!(phase, Some(GamePhase::Night { .. })));
+        assert!(game.get_winner().is_none());
+    }
+}
+
+#[test]
+fn a_substitute_takes_over_the_players_place() {
+    let mut game = start_game(&[]);
+    let mafia_id = mafia_id(&game);
+    let civilian_ids = civilian_ids(&game);
+    let substitute_id = PlayerId(9);
+
+    vote(&mut game, mafia_id, civilian_ids[0]);
+    game.substitute_player(mafia_id, substitute_id, String::from("Player9"))
+        .unwrap();
+
+    // The substitute is the Mafia now, and keeps the vote that was already made
+    let substitute = game
+        .get_players()
+        .into_iter()
+        .find(|p| p.player_id == substitute_id)
+        .unwrap();
+    assert_eq!(substitute.role, Role::Mafia);
+    assert!(substitute.is_alive());
+    assert!(!is_idle(&game, substitute_id));
+    assert!(!game.get_players().iter().any(|p| p.player_id == mafia_id));
+    assert!(game
+        .substitute_player(civilian_ids[0], substitute_id, String::from("Player9"))
+        .is_err());
+
+    for voter_id in civilian_ids.iter() {
+        vote(&mut game, *voter_id, substitute_id);
+    }
+    assert!(matches!(
+        game.end_phase(),
+        Some(GamePhase::Trial { defendant_id, .. }) if *defendant_id == substitute_id
+    ));
+
+    let summary = game.get_summary();
+    assert!(summary.contains(&format!("Player{mafia_id}: Mafia, replaced by Player9")));
+    assert!(summary.contains("Player9: Mafia, survived"));
+    assert!(summary.contains(&format!("Player9 took over from Player{mafia_id}")));
+    // The round ended after the substitution, so its votes are the substitute's
+    assert!(summary.contains(&format!("\nPlayer9 → Player{}", civilian_ids[0])));
+}
@@ -0,0 +1,90 @@
+//! Checks the host's control over a running game
+
+use that_mafia_game::game::{game_v1::GameV1, Game, GameError, PlayerId, Role};
+
+use common::{civilian_ids, make_lobby, vote};
+
+mod common;
+
+/// Starts a day-start game between Player1 to Player5, with a single Mafia, hosted by Player2
+fn start_game() -> GameV1 {
+    let mut lobby = make_lobby(5, &[("mafia", "1"), ("start", "day")]);
+    lobby.host_id = PlayerId(2);
+    GameV1::from_lobby(&lobby, 0)
+}
+
+#[test]
+fn the_host_carries_over_from_the_lobby_and_passes_on() {
+    let mut game = start_game();
+    assert_eq!(game.get_host_id(), Some(PlayerId(2)));
+
+    game.substitute_player(PlayerId(2), PlayerId(9), String::from("Player9"))
+        .unwrap();
+    assert_eq!(game.get_host_id(), Some(PlayerId(9)));
+
+    game.quit(PlayerId(9)).unwrap();
+    assert_eq!(game.get_host_id(), Some(PlayerId(1)));
+}
+
+#[test]
+fn hosting_passes_to_the_first_player_still_alive() {
+    let mut lobby = make_lobby(6, &[("mafia", "1"), ("start", "day")]);
+    lobby.host_id = PlayerId(3);
+    let mut game = GameV1::from_lobby(&lobby, 0);
+    assert_eq!(game.get_players()[3].role, Role::Mafia);
+
+    // Player1 is dead and Player2 has left, so neither can host
+    game.modkill(PlayerId(1)).unwrap();
+    game.quit(PlayerId(2)).unwrap();
+    game.quit(PlayerId(3)).unwrap();
+    assert_eq!(game.get_host_id(), Some(PlayerId(4)));
+}
+
+#[test]
+fn a_paused_game_takes_no_inputs() {
+    let mut game = start_game();
+    let voter_id = civilian_ids(&game)[0];
+
+    game.set_paused(true).unwrap();
+    assert!(game.is_paused());
+    assert_eq!(game.add_vote(voter_id, vec![0]), Err(GameError::Paused));
+    for player_id in civilian_ids(&game) {
+        assert!(game
+            .get_idle_players()
+            .iter()
+            .any(|p| p.player_id == player_id));
+    }
+
+    game.set_paused(false).unwrap();
+    game.add_vote(voter_id, vec![0]).unwrap();
+}
+
+#[test]
+fn modkilled_players_die_and_lose_their_inputs() {
+    let mut game = start_game();
+    let civilian_ids = civilian_ids(&game);
+    let (voter_id, target_id) = (civilian_ids[0], civilian_ids[1]);
+    vote(&mut game, voter_id, target_id);
+
+    assert!(game.modkill(target_id).unwrap().is_none());
+    assert!(game.modkill(target_id).is_err());
+
+    // The player is dead, but still in the game
+    let target = game
+        .get_players()
+        .into_iter()
+        .find(|p| p.player_id == target_id)
+        .unwrap();
+    assert!(!target.is_alive());
+    assert!(game
+        .get_idle_players()
+        .iter()
+        .any(|p| p.player_id == voter_id));
+    assert!(game.get_private_messages()[&target_id]
+        .last()
+        .unwrap()
+        .starts_with("You are dead"));
+    assert!(game.get_summary().contains(&format!(
+        "Player{target_id} was removed by the host on day 1"
+    )));
+}
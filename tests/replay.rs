@@ -0,0 +1,174 @@
+//! Checks that a game can be rebuilt from the events it records
+
+use that_mafia_game::game::{game_v1::GameV1, Game, GameEvent, GamePhase, PlayerId, Role};
+
+use common::{
+    civilian_ids, get_ids, give_verdicts, mafia_id, make_lobby, put_on_trial, start_game, to_value,
+    vote,
+};
+
+mod common;
+
+/// Plays a game where every player picks their first option, except for Detectives, who never
+/// choose so that nights end at their deadline. Returns the game with every event it recorded
+fn play_game(seed: u64) -> (GameV1, Vec<GameEvent>) {
+    let mut game = GameV1::from_lobby(&make_lobby(7, &[]), seed);
+    let mut events = game.take_events();
+
+    while !matches!(game.get_phase(), GamePhase::Ending) {
+        assert!(game.get_phase_number() < 100, "Game did not end");
+        match game.get_phase() {
+            GamePhase::Night { .. } => {
+                for (player_id, (_, options)) in game.get_night_actions() {
+                    let is_detective = game
+                        .get_players()
+                        .iter()
+                        .any(|p| p.player_id == player_id && p.role == Role::Detective);
+                    if let (Some((target_id, _)), false) = (options.first(), is_detective) {
+                        game.add_night_action(player_id, *target_id).unwrap();
+                    }
+                }
+            }
+            GamePhase::Voting { .. } => {
+                for player_id in get_ids(game.get_voters().unwrap()) {
+                    game.add_vote(player_id, vec![0]).unwrap();
+                }
+            }
+            GamePhase::Trial { .. } => {
+                for player_id in get_ids(game.get_jury().unwrap()) {
+                    game.add_verdict(player_id, 0).unwrap();
+                }
+            }
+            GamePhase::Defense { .. } | GamePhase::LastWords { .. } | GamePhase::Ending => {}
+        }
+
+        if game.end_phase().is_none() {
+            game.force_end_phase();
+        }
+        events.extend(game.take_events());
+    }
+
+    (game, events)
+}
+
+/// Inputs played on a game from `common::start_game` with the given setup options
+struct Scenario {
+    name: &'static str,
+    options: &'static [(&'static str, &'static str)],
+    play: fn(&mut GameV1),
+}
+
+/// A civilian's vote is dropped when the player they voted for quits, and the Mafia hands their
+/// place to a substitute
+fn quit_and_substitute(game: &mut GameV1) {
+    let civilian_ids = civilian_ids(game);
+    vote(game, civilian_ids[0], civilian_ids[1]);
+    game.quit(civilian_ids[1]).unwrap();
+    game.substitute_player(mafia_id(game), PlayerId(9), String::from("Player9"))
+        .unwrap();
+    game.force_end_phase().unwrap();
+}
+
+/// The host pauses the game, ends the voting, resumes it and modkills a civilian
+fn moderate(game: &mut GameV1) {
+    game.set_paused(true).unwrap();
+    game.force_end_phase().unwrap();
+    game.set_paused(false).unwrap();
+    game.modkill(civilian_ids(game)[0]).unwrap();
+}
+
+/// A civilian defends themselves, is lynched and says their last words
+fn defend_and_say_last_words(game: &mut GameV1) {
+    put_on_trial(game, civilian_ids(game)[0]);
+    game.force_end_phase().unwrap();
+    give_verdicts(game, 0);
+    game.end_phase().unwrap();
+    game.force_end_phase().unwrap();
+}
+
+#[test]
+fn replaying_the_events_rebuilds_the_game() {
+    for seed in 0..20 {
+        let (game, events) = play_game(seed);
+        let replayed = GameV1::replay(&events).unwrap();
+
+        assert_eq!(to_value(&replayed), to_value(&game), "Seed {seed}");
+        assert_eq!(replayed.get_winner(), game.get_winner());
+    }
+}
+
+#[test]
+fn scripted_scenarios_are_replayed() {
+    let scenarios = [
+        Scenario {
+            name: "quit and substitute",
+            options: &[],
+            play: quit_and_substitute,
+        },
+        Scenario {
+            name: "moderate",
+            options: &[],
+            play: moderate,
+        },
+        Scenario {
+            name: "defend and say last words",
+            options: &[("defense", "on"), ("lastwords", "on")],
+            play: defend_and_say_last_words,
+        },
+    ];
+    for scenario in scenarios {
+        let mut game = start_game(scenario.options);
+        let mut events = game.take_events();
+        (scenario.play)(&mut game);
+        events.extend(game.take_events());
+
+        let replayed = GameV1::replay(&events).unwrap();
+        assert_eq!(to_value(&replayed), to_value(&game), "{}", scenario.name);
+    }
+}
+
+#[test]
+fn replay_can_be_stepped_through() {
+    let (game, events) = play_game(3);
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, GameEvent::PhaseForced)));
+
+    let mut replayed = GameV1::replay(&events[..1]).unwrap();
+    for event in events[1..].iter() {
+        let phase_number = replayed.get_phase_number();
+        replayed.apply_event(event).unwrap();
+
+        let ends_phase = matches!(event, GameEvent::PhaseEnded | GameEvent::PhaseForced);
+        assert_eq!(
+            replayed.get_phase_number(),
+            phase_number + ends_phase as usize
+        );
+    }
+
+    assert_eq!(to_value(&replayed), to_value(&game));
+    // Applied events are recorded like the original inputs were
+    assert_eq!(replayed.take_events().len(), events.len() - 1);
+}
+
+#[test]
+fn replay_rejects_events_that_do_not_apply() {
+    let (_, mut events) = play_game(3);
+    assert!(GameV1::replay(&events[1..]).is_err());
+
+    // A player cannot vote twice
+    let first_vote = events
+        .iter()
+        .position(|event| matches!(event, GameEvent::Vote { .. }))
+        .unwrap();
+    events.insert(first_vote, events[first_vote].clone());
+
+    let err = GameV1::replay(&events).err().unwrap();
+    assert_eq!(
+        err,
+        format!(
+            "Failed to apply event {}: You have already made your choice",
+            first_vote + 1
+        )
+    );
+}
@@ -0,0 +1,32 @@
+//! Checks that simulated games only depend on their seed
+
+use that_mafia_game::{
+    game::game_setup::GameSetup,
+    simulator::{policies::get_policy_by_name, Simulator},
+};
+
+fn make_simulator() -> Simulator {
+    Simulator {
+        player_count: 7,
+        setup: GameSetup::default(),
+        mafia_policy: get_policy_by_name("random").unwrap(),
+        town_policy: get_policy_by_name("random").unwrap(),
+        max_phases: 200,
+    }
+}
+
+#[test]
+fn a_fixed_seed_replays_the_same_run() {
+    let simulator = make_simulator();
+    for seed in 0..20 {
+        let (first, second) = (simulator.run_game(seed), make_simulator().run_game(seed));
+        assert_eq!(first.winner, second.winner, "seed {seed}");
+        assert_eq!(first.phase_count, second.phase_count, "seed {seed}");
+        assert_eq!(first.transitions, second.transitions, "seed {seed}");
+    }
+
+    assert_eq!(
+        simulator.run_games(50, 7).to_string(),
+        make_simulator().run_games(50, 7).to_string()
+    );
+}
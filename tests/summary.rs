@@ -0,0 +1,50 @@
+//! Checks the summary that players are shown once a game ends
+
+use that_mafia_game::game::{game_v1::GameV1, Game};
+
+use common::{civilian_ids, mafia_id, make_lobby, vote};
+
+mod common;
+
+#[test]
+fn summary_lists_roles_fates_votes_and_verdicts() {
+    let mut game = GameV1::from_lobby(&make_lobby(4, &[("start", "day")]), 0);
+    let mafia_id = mafia_id(&game);
+    let civilian_ids = civilian_ids(&game);
+
+    // Everyone but the Mafia votes for the Mafia, who does not vote
+    for player_id in civilian_ids.iter() {
+        vote(&mut game, *player_id, mafia_id);
+    }
+    game.force_end_phase().unwrap();
+
+    game.add_verdict(civilian_ids[0], 0).unwrap();
+    game.add_verdict(civilian_ids[1], 0).unwrap();
+    game.add_verdict(civilian_ids[2], 1).unwrap();
+    game.end_phase().unwrap();
+    assert_eq!(game.get_winner().as_deref(), Some("Civilians"));
+
+    let mafia = format!("Player{mafia_id}");
+    let civilians = civilian_ids
+        .iter()
+        .map(|id| format!("Player{id}"))
+        .collect::<Vec<_>>();
+    let summary = game.get_summary();
+
+    assert!(summary.starts_with("Roles:\n"));
+    assert!(summary.contains(&format!("{mafia}: Mafia, lynched on day 1")));
+    for civilian in civilians.iter() {
+        assert!(summary.contains(&format!("{civilian}: ")));
+        assert!(!summary.contains(&format!("{civilian}: Mafia")));
+    }
+    assert_eq!(summary.matches(", survived").count(), 3);
+
+    assert!(summary.contains("\n\nDay 1 votes:\n"));
+    assert!(summary.contains(&format!("\n{mafia} → did not vote")));
+    assert!(summary.contains(&format!("\n{} → {mafia}", civilians[0])));
+
+    // The jurors' verdicts stay anonymous
+    assert!(summary.ends_with(&format!(
+        "Day 1 trial of {mafia}: lynched\nGuilty: 2\nInnocent: 1"
+    )));
+}
@@ -0,0 +1,720 @@
+//! Runs the bot's handlers end-to-end against a fake Telegram Bot API
+
+use rand::{rngs::StdRng, SeedableRng};
+use std::{env, fs, process, time::Duration};
+use that_mafia_game::{open_async_bot_state, run_mafia_bot, PhaseDurations};
+use tokio::task::JoinHandle;
+
+use fake_telegram::{FakeTelegram, Request};
+
+mod fake_telegram;
+
+/// Long enough that no deadline passes during a test
+const NO_DEADLINES: PhaseDurations = PhaseDurations {
+    night: Duration::from_secs(600),
+    voting: Duration::from_secs(600),
+    defense: Duration::from_secs(600),
+    trial: Duration::from_secs(600),
+    last_words: Duration::from_secs(600),
+    warning: Duration::from_secs(30),
+};
+
+/// Starts a bot with an empty database, talking to a new fake Telegram
+fn start_bot(phase_durations: PhaseDurations) -> FakeTelegram {
+    let telegram = FakeTelegram::start();
+    start_bot_with_database(&telegram, ":memory:", phase_durations);
+    telegram
+}
+
+fn start_bot_with_database(
+    telegram: &FakeTelegram,
+    database_path: &str,
+    phase_durations: PhaseDurations,
+) -> JoinHandle<()> {
+    let bot_state = open_async_bot_state(database_path, phase_durations, StdRng::seed_from_u64(0));
+    tokio::spawn(run_mafia_bot(telegram.bot(), bot_state))
+}
+
+/// Has player 1 host a lobby that players 2 to `player_count` join, returning the lobby's code
+async fn host_lobby(telegram: &FakeTelegram, player_count: i64) -> String {
+    telegram.send_text(1, "/host");
+    let reply = telegram.wait_for_message(1, "Created new lobby").await;
+    let code = reply.text().rsplit(' ').next().unwrap().to_string();
+
+    for player_id in 2..=player_count {
+        telegram.send_text(player_id, &format!("/join {code}"));
+        telegram.wait_for_message(player_id, "Joined lobby").await;
+    }
+    code
+}
+
+#[tokio::test]
+async fn players_can_host_and_join_a_lobby() {
+    let telegram = start_bot(NO_DEADLINES);
+    let code = host_lobby(&telegram, 3).await;
+
+    telegram.send_text(4, "/join 1");
+    telegram.wait_for_message(4, "Encountered error").await;
+
+    telegram.send_text(2, "/players");
+    let reply = telegram.wait_for_message(2, "(host)").await;
+    assert!(reply
+        .text()
+        .contains("1. player1 (host)\n2. player2\n3. player3"));
+
+    telegram.send_text(1, "/start");
+    telegram.wait_for_message(1, "Started lobby").await;
+    telegram.send_text(4, &format!("/join {code}"));
+    telegram.wait_for_message(4, "Encountered error").await;
+}
+
+#[tokio::test]
+async fn mafia_kill_ends_a_three_player_game() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 3).await;
+    telegram.send_text(1, "/start");
+
+    let prompt = telegram
+        .wait_for(|r| r.method == "sendMessage" && r.text().starts_with("You are a Mafia"))
+        .await;
+    let mafia_id = prompt.chat_id();
+    let victim = prompt
+        .option_texts()
+        .into_iter()
+        .find(|option| option != "Nobody")
+        .unwrap();
+
+    telegram.press_button(mafia_id, &prompt, &victim);
+    telegram
+        .wait_for(|r| r.method == "answerCallbackQuery" && r.params["text"].is_null())
+        .await;
+    telegram
+        .wait_for(|r| {
+            r.method == "editMessageText" && r.text().ends_with(&format!("You chose: {victim}"))
+        })
+        .await;
+
+    for player_id in 1..=3 {
+        telegram
+            .wait_for_message(player_id, "Mafia won the game!")
+            .await;
+        let summary = telegram.wait_for_message(player_id, "Game summary").await;
+        assert!(summary
+            .text()
+            .contains(&format!("{victim}: Civilian, killed on night 1")));
+        assert!(summary.text().ends_with(&format!("Night 1: {victim} died")));
+    }
+}
+
+#[tokio::test]
+async fn voting_and_trial_lynch_the_defendant() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 4).await;
+    telegram.send_text(1, "/set start day");
+    telegram.wait_for_message(1, "Updated game setup").await;
+    telegram.send_text(1, "/start");
+
+    let polls = telegram
+        .wait_for_all(4, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+    for poll in polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "player1");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    // Each answered poll is closed
+    telegram
+        .wait_for_all(4, |r| {
+            r.method == "stopPoll" && r.result["is_closed"] == true
+        })
+        .await;
+
+    let polls = telegram
+        .wait_for_all(3, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Vote on trial: "
+        })
+        .await;
+    for poll in polls.iter() {
+        assert_ne!(
+            poll.chat_id(),
+            1,
+            "The defendant does not vote on their trial"
+        );
+        let option = poll.option_texts().iter().position(|o| o == "Guilty");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+
+    telegram
+        .wait_for_message(
+            2,
+            "By a vote of 3 guilty to 0 innocent, player1 was lynched",
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn answers_to_polls_from_an_earlier_phase_have_expired() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 4).await;
+    telegram.send_text(1, "/set start day");
+    telegram.wait_for_message(1, "Updated game setup").await;
+    telegram.send_text(1, "/start");
+
+    let vote_polls = telegram
+        .wait_for_all(4, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+    for poll in vote_polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "player1");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    let trial_polls = telegram
+        .wait_for_all(3, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Vote on trial: "
+        })
+        .await;
+
+    // The first option of the vote poll is not taken as a Guilty verdict
+    let juror_id = trial_polls[0].chat_id();
+    let vote_poll = vote_polls.iter().find(|r| r.chat_id() == juror_id);
+    telegram.answer_poll(juror_id, vote_poll.unwrap(), &[0]);
+    telegram
+        .wait_for_message(juror_id, "This choice has expired")
+        .await;
+
+    for poll in trial_polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "Innocent");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    telegram
+        .wait_for_message(juror_id, "By a vote of 3 innocent to 0 guilty")
+        .await;
+}
+
+#[tokio::test]
+async fn idle_players_are_warned_and_the_night_ends_at_its_deadline() {
+    let telegram = start_bot(PhaseDurations {
+        night: Duration::from_secs(2),
+        warning: Duration::from_secs(1),
+        ..NO_DEADLINES
+    });
+    host_lobby(&telegram, 3).await;
+    telegram.send_text(1, "/start");
+
+    let prompt = telegram
+        .wait_for(|r| r.method == "sendMessage" && r.text().starts_with("You are a Mafia"))
+        .await;
+    telegram
+        .wait_for_message(prompt.chat_id(), "1 seconds left to make your choice!")
+        .await;
+
+    // Nobody was killed, so every player gets to vote
+    telegram.wait_for_all(3, |r| r.method == "sendPoll").await;
+    let warnings = telegram
+        .wait_for_all(1, |r| r.text().contains("seconds left"))
+        .await;
+    assert_eq!(warnings.len(), 1, "Only the Mafia had a choice to make");
+}
+
+#[tokio::test]
+async fn buttons_with_unknown_data_have_expired() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 3).await;
+    telegram.send_text(1, "/start");
+
+    let prompt = telegram
+        .wait_for(|r| r.method == "sendMessage" && r.text().starts_with("You are a Mafia"))
+        .await;
+    // Keyboards sent before the phase number was added only carry the target's id
+    let target_id = prompt
+        .button_data("Nobody")
+        .rsplit(':')
+        .next()
+        .unwrap()
+        .to_string();
+    telegram.press_button_with_data(prompt.chat_id(), &prompt, &target_id);
+    telegram
+        .wait_for(|r| {
+            r.method == "answerCallbackQuery" && r.params["text"] == "This choice has expired"
+        })
+        .await;
+
+    telegram.press_button(prompt.chat_id(), &prompt, "Nobody");
+    telegram
+        .wait_for_all(3, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn a_player_who_blocked_the_bot_does_not_stop_the_game() {
+    let telegram = start_bot(PhaseDurations {
+        voting: Duration::from_secs(2),
+        warning: Duration::from_secs(1),
+        ..NO_DEADLINES
+    });
+    host_lobby(&telegram, 4).await;
+    telegram.send_text(1, "/set start day");
+    telegram.wait_for_message(1, "Updated game setup").await;
+    telegram.block_bot(4);
+    telegram.send_text(1, "/start");
+
+    // Nobody votes, so the vote is held again once its deadline passes
+    let is_vote_poll =
+        |r: &Request| r.method == "sendPoll" && r.params["question"] == "Who to put on trial?";
+    let polls = telegram.wait_for_all(6, is_vote_poll).await;
+    assert!(polls.iter().all(|r| r.chat_id() != 4));
+}
+
+#[tokio::test]
+async fn the_night_ends_at_its_deadline_even_if_a_player_blocked_the_bot() {
+    let telegram = start_bot(PhaseDurations {
+        night: Duration::from_secs(2),
+        warning: Duration::from_secs(1),
+        ..NO_DEADLINES
+    });
+    host_lobby(&telegram, 3).await;
+    telegram.block_bot(3);
+    telegram.send_text(1, "/start");
+
+    // Nobody was killed, so both reachable players get to vote
+    telegram
+        .wait_for_all(2, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn a_player_who_blocked_the_bot_does_not_stop_the_others_night_messages() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 5).await;
+    telegram.send_text(1, "/set start day");
+    telegram.wait_for_message(1, "Updated game setup").await;
+    telegram.block_bot(5);
+    telegram.send_text(1, "/start");
+
+    let polls = telegram
+        .wait_for_all(4, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+    for poll in polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "player2");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    telegram.send_text(1, "/next");
+    let polls = telegram
+        .wait_for_all(3, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Vote on trial: "
+        })
+        .await;
+    for poll in polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "Guilty");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    telegram.send_text(1, "/next");
+
+    // The lynched player is welcomed to the graveyard and every living player is prompted, even
+    // though player5 cannot be reached
+    telegram.wait_for_message(2, "You are dead").await;
+    let prompts = telegram
+        .wait_for_all(3, |r| {
+            r.method == "sendMessage" && r.text().starts_with("You are a") && r.chat_id() != 5
+        })
+        .await;
+    let mut prompted_ids = prompts.iter().map(|r| r.chat_id()).collect::<Vec<_>>();
+    prompted_ids.sort();
+    assert_eq!(prompted_ids, vec![1, 3, 4]);
+}
+
+#[tokio::test]
+async fn the_game_moves_on_when_telegram_rejects_closing_polls_and_answering_buttons() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 3).await;
+    telegram.fail_method("answerCallbackQuery");
+    telegram.fail_method("stopPoll");
+    telegram.send_text(1, "/start");
+
+    let prompt = telegram
+        .wait_for(|r| r.method == "sendMessage" && r.text().starts_with("You are a Mafia"))
+        .await;
+    telegram.press_button(prompt.chat_id(), &prompt, "Nobody");
+
+    let polls = telegram
+        .wait_for_all(3, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+    for poll in polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "player1");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    let polls = telegram
+        .wait_for_all(2, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Vote on trial: "
+        })
+        .await;
+    for poll in polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "Innocent");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    telegram
+        .wait_for_message(2, "By a vote of 2 innocent to 0 guilty")
+        .await;
+}
+
+#[tokio::test]
+async fn games_resume_after_a_restart() {
+    let database_path = env::temp_dir().join(format!("that_mafia_game_{}.db", process::id()));
+    let database_path = database_path.to_str().unwrap();
+    let telegram = FakeTelegram::start();
+    let bot = start_bot_with_database(&telegram, database_path, NO_DEADLINES);
+
+    host_lobby(&telegram, 4).await;
+    telegram.send_text(1, "/start");
+    let is_mafia_prompt =
+        |r: &Request| r.method == "sendMessage" && r.text().starts_with("You are a Mafia");
+    let is_doctor_prompt =
+        |r: &Request| r.method == "sendMessage" && r.text().starts_with("You are a Doctor");
+    telegram.wait_for(is_mafia_prompt).await;
+    let prompt = telegram.wait_for(is_doctor_prompt).await;
+    telegram.press_button(prompt.chat_id(), &prompt, "player1");
+    telegram
+        .wait_for(|r| r.method == "editMessageText" && r.text().ends_with("You chose: player1"))
+        .await;
+
+    bot.abort();
+    start_bot_with_database(&telegram, database_path, NO_DEADLINES);
+
+    // Only the Mafia, who has not chosen yet, is prompted again, and the new prompt can be
+    // answered
+    let prompt = telegram.wait_for_all(2, is_mafia_prompt).await.remove(1);
+    telegram.press_button(prompt.chat_id(), &prompt, "Nobody");
+    telegram
+        .wait_for_all(4, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+    let requests = telegram.requests();
+    assert_eq!(requests.iter().filter(|r| is_doctor_prompt(r)).count(), 1);
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|r| r.text().starts_with("You are a Civilian"))
+            .count(),
+        2
+    );
+
+    fs::remove_file(database_path).ok();
+}
+
+#[tokio::test]
+async fn a_player_can_quit_a_running_game() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 3).await;
+    telegram.send_text(1, "/start");
+
+    let prompt = telegram
+        .wait_for(|r| r.method == "sendMessage" && r.text().starts_with("You are a Mafia"))
+        .await;
+    let mafia_id = prompt.chat_id();
+    let quitter_id = (1..=3).find(|id| *id != mafia_id).unwrap();
+
+    // Only the Mafia and one other player are left, so the Mafia win
+    telegram.send_text(quitter_id, "/quit");
+    telegram
+        .wait_for_message(quitter_id, "You have left the game")
+        .await;
+    for player_id in (1..=3).filter(|id| *id != quitter_id) {
+        telegram
+            .wait_for_message(player_id, &format!("player{quitter_id} quit the game"))
+            .await;
+        telegram
+            .wait_for_message(player_id, "Mafia won the game!")
+            .await;
+    }
+    assert!(!telegram
+        .requests()
+        .iter()
+        .any(|r| r.is_message_to(quitter_id, "won the game")));
+
+    // The player is back in the main menu
+    telegram.send_text(quitter_id, "/host");
+    telegram
+        .wait_for_message(quitter_id, "Created new lobby")
+        .await;
+}
+
+#[tokio::test]
+async fn a_substitute_takes_over_a_players_place() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 3).await;
+    telegram.send_text(1, "/start");
+
+    let prompt = telegram
+        .wait_for(|r| r.method == "sendMessage" && r.text().starts_with("You are a Mafia"))
+        .await;
+    let mafia_id = prompt.chat_id();
+
+    telegram.send_text(mafia_id, "/substitute");
+    let reply = telegram
+        .wait_for_message(mafia_id, "Ask your substitute")
+        .await;
+    let code = reply
+        .text()
+        .split_whitespace()
+        .find_map(|word| word.parse::<i32>().ok())
+        .unwrap();
+
+    telegram.send_text(4, &format!("/join {code}"));
+    telegram
+        .wait_for_message(
+            4,
+            &format!("taken over player{mafia_id}'s place. You are a Mafia"),
+        )
+        .await;
+    telegram
+        .wait_for_message(mafia_id, "player4 has taken over your place")
+        .await;
+
+    // The substitute gets the Mafia's prompt, and their choice ends the game
+    let prompt = telegram
+        .wait_for(|r| r.is_message_to(4, "You are a Mafia") && !r.option_texts().is_empty())
+        .await;
+    let victim = prompt
+        .option_texts()
+        .into_iter()
+        .find(|option| option != "Nobody")
+        .unwrap();
+    telegram.press_button(4, &prompt, &victim);
+
+    let summary = telegram.wait_for_message(4, "Game summary").await;
+    assert!(summary
+        .text()
+        .contains(&format!("player{mafia_id}: Mafia, replaced by player4")));
+
+    // The code only works once
+    telegram.send_text(5, &format!("/join {code}"));
+    telegram.wait_for_message(5, "Encountered error").await;
+}
+
+#[tokio::test]
+async fn a_failed_substitution_keeps_the_code_and_the_dead_cannot_be_substituted() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 5).await;
+    telegram.send_text(1, "/start");
+
+    let prompt = telegram
+        .wait_for(|r| r.method == "sendMessage" && r.text().starts_with("You are a Mafia"))
+        .await;
+    let mafia_id = prompt.chat_id();
+    let other_ids = (2..=5).filter(|id| *id != mafia_id).collect::<Vec<_>>();
+    let (dead_id, quitter_id) = (other_ids[0], other_ids[1]);
+
+    telegram.send_text(1, &format!("/modkill {dead_id}"));
+    telegram.wait_for_message(dead_id, "You are dead").await;
+    telegram.send_text(dead_id, "/substitute");
+    telegram
+        .wait_for_message(dead_id, "Only living players can be substituted")
+        .await;
+
+    telegram.send_text(quitter_id, "/quit");
+    telegram
+        .wait_for_message(quitter_id, "You have left the game")
+        .await;
+    telegram.send_text(mafia_id, "/substitute");
+    let reply = telegram
+        .wait_for_message(mafia_id, "Ask your substitute")
+        .await;
+    let code = reply
+        .text()
+        .split_whitespace()
+        .find_map(|word| word.parse::<i32>().ok())
+        .unwrap();
+
+    // A player who left cannot come back as a substitute, and their attempt does not use up
+    // the code
+    telegram.send_text(quitter_id, &format!("/join {code}"));
+    telegram
+        .wait_for_message(quitter_id, "You have already played in that game")
+        .await;
+    telegram.send_text(quitter_id, "/host");
+    telegram
+        .wait_for_message(quitter_id, "Created new lobby")
+        .await;
+
+    telegram.send_text(6, &format!("/join {code}"));
+    telegram
+        .wait_for_message(6, &format!("taken over player{mafia_id}'s place"))
+        .await;
+}
+
+#[tokio::test]
+async fn the_host_can_kick_players_from_the_lobby() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 4).await;
+
+    telegram.send_text(2, "/kick 3");
+    telegram
+        .wait_for_message(2, "Only the host can remove players")
+        .await;
+    telegram.send_text(1, "/kick 3");
+    telegram
+        .wait_for_message(1, "Removed player3 from the lobby")
+        .await;
+    telegram
+        .wait_for_message(3, "The host removed you from the lobby")
+        .await;
+
+    // Players who quit are gone from the lobby too
+    telegram.send_text(4, "/quit");
+    telegram.wait_for_message(4, "Quit lobby").await;
+    telegram.send_text(1, "/players");
+    let reply = telegram.wait_for_message(1, "(host)").await;
+    assert!(reply
+        .text()
+        .starts_with("1. player1 (host)\n2. player2\n\n"));
+}
+
+#[tokio::test]
+async fn the_host_can_pause_resume_and_end_a_phase() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 4).await;
+    telegram.send_text(1, "/start");
+
+    let is_mafia_prompt =
+        |r: &Request| r.method == "sendMessage" && r.text().starts_with("You are a Mafia");
+    let prompt = telegram.wait_for(is_mafia_prompt).await;
+    let mafia_id = prompt.chat_id();
+
+    telegram.send_text(2, "/pause");
+    telegram
+        .wait_for_message(2, "Only the host can do that")
+        .await;
+    telegram.send_text(1, "/pause");
+    telegram
+        .wait_for_message(mafia_id, "The host has paused the game")
+        .await;
+
+    telegram.press_button(mafia_id, &prompt, "Nobody");
+    telegram
+        .wait_for(|r| r.method == "answerCallbackQuery" && r.params["text"] == "The game is paused")
+        .await;
+    telegram.send_text(1, "/next");
+    telegram
+        .wait_for_message(1, "Resume the game before ending the phase")
+        .await;
+
+    // The idle Mafia is prompted again once the game is resumed
+    telegram.send_text(1, "/resume");
+    telegram
+        .wait_for_message(mafia_id, "The host has resumed the game")
+        .await;
+    telegram.wait_for_all(2, is_mafia_prompt).await;
+
+    telegram.send_text(1, "/next");
+    telegram
+        .wait_for_message(mafia_id, "The host has ended the phase early")
+        .await;
+    telegram
+        .wait_for_all(4, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn the_host_can_modkill_a_player() {
+    let telegram = start_bot(NO_DEADLINES);
+    host_lobby(&telegram, 4).await;
+    telegram.send_text(1, "/set start day");
+    telegram.wait_for_message(1, "Updated game setup").await;
+    telegram.send_text(1, "/start");
+    telegram.wait_for_all(4, |r| r.method == "sendPoll").await;
+
+    telegram.send_text(1, "/modkill 4");
+    for player_id in 1..=4 {
+        telegram
+            .wait_for_message(player_id, "player4 was removed from the game by the host")
+            .await;
+    }
+    telegram.wait_for_message(4, "You are dead").await;
+
+    // The vote options have changed, so the living players are sent new polls without player4
+    let polls = telegram
+        .wait_for_all(7, |r| r.method == "sendPoll")
+        .await
+        .split_off(4);
+    assert_eq!(polls.len(), 3);
+    for poll in polls {
+        assert_ne!(poll.chat_id(), 4);
+        assert!(!poll.option_texts().contains(&String::from("player4")));
+    }
+
+    telegram.send_text(1, "/players");
+    let reply = telegram.wait_for_message(1, "(host)").await;
+    assert!(reply.text().ends_with("4. player4 (dead)"));
+}
+
+#[tokio::test]
+async fn the_defendant_speaks_before_the_trial_and_the_lynched_say_last_words() {
+    let telegram = start_bot(PhaseDurations {
+        last_words: Duration::from_secs(2),
+        warning: Duration::from_secs(1),
+        ..NO_DEADLINES
+    });
+    host_lobby(&telegram, 6).await;
+    for setting in ["start day", "mafia 2", "defense on", "lastwords on"] {
+        telegram.send_text(1, &format!("/set {setting}"));
+    }
+    telegram
+        .wait_for_all(4, |r| r.is_message_to(1, "Updated game setup"))
+        .await;
+    telegram.send_text(1, "/start");
+
+    let polls = telegram
+        .wait_for_all(6, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Who to put on trial?"
+        })
+        .await;
+    for poll in polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "player2");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    telegram
+        .wait_for_message(3, "player2 has been put on trial")
+        .await;
+
+    telegram.send_text(3, "Guilty!");
+    telegram
+        .wait_for_message(3, "Only player2 may speak during their defense")
+        .await;
+    telegram.send_text(2, "I am innocent");
+    telegram.wait_for_message(3, "player2: I am innocent").await;
+
+    // The defense lasts until its deadline, or until the host moves the game on
+    telegram.send_text(1, "/next");
+    let polls = telegram
+        .wait_for_all(5, |r| {
+            r.method == "sendPoll" && r.params["question"] == "Vote on trial: "
+        })
+        .await;
+    for poll in polls.iter() {
+        let option = poll.option_texts().iter().position(|o| o == "Guilty");
+        telegram.answer_poll(poll.chat_id(), poll, &[option.unwrap() as i32]);
+    }
+    telegram
+        .wait_for_message(3, "player2 may now say their last words")
+        .await;
+
+    telegram.send_text(2, "Goodbye");
+    telegram.wait_for_message(3, "player2: Goodbye").await;
+    telegram
+        .wait_for_message(3, "player2's last words are over")
+        .await;
+    telegram.wait_for_message(2, "You are dead").await;
+}
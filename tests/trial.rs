@@ -0,0 +1,41 @@
+//! Checks how a trial's verdicts decide the defendant's fate
+
+use that_mafia_game::game::{Game, GameError, GamePhase};
+
+use common::{civilian_ids, put_on_trial, start_game};
+
+mod common;
+
+#[test]
+fn a_trial_without_verdicts_releases_the_defendant() {
+    let mut game = start_game(&[]);
+    let defendant_id = civilian_ids(&game)[0];
+    put_on_trial(&mut game, defendant_id);
+    assert!(matches!(game.get_phase(), GamePhase::Trial { .. }));
+
+    // Nobody gave a verdict before the trial's deadline
+    assert!(matches!(
+        game.force_end_phase(),
+        Some(GamePhase::Night { .. })
+    ));
+    assert_eq!(
+        game.get_transition_message(),
+        format!("By a vote of 0 innocent to 0 guilty, Player{defendant_id} was released")
+    );
+    assert!(game
+        .get_players()
+        .iter()
+        .any(|p| p.player_id == defendant_id && p.is_alive()));
+}
+
+#[test]
+fn verdicts_outside_a_trial_are_in_the_wrong_phase_even_from_the_dead() {
+    let mut game = start_game(&[]);
+    let civilian_ids = civilian_ids(&game);
+    let (dead_id, defendant_id) = (civilian_ids[0], civilian_ids[1]);
+    game.modkill(dead_id).unwrap();
+
+    assert_eq!(game.add_verdict(dead_id, 0), Err(GameError::WrongPhase));
+    put_on_trial(&mut game, defendant_id);
+    assert_eq!(game.add_verdict(dead_id, 0), Err(GameError::NotAlive));
+}